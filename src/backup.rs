@@ -0,0 +1,537 @@
+use crate::context::StoreContext;
+use crate::keygen_state_machine::{verify_shares, WstsState};
+use crate::preprocessing_state_machine::NoncePool;
+use crate::schema;
+use crate::store::{KeyId, StoreError};
+use crate::utils::OutputFormat;
+use blueprint_sdk::logging::{info, warn};
+use blueprint_sdk::macros::ext::contexts::tangle::TangleClientContext;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::Field;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use gadget_macros::ext::clients::GadgetServicesClient;
+use p256k1::point::Point;
+use p256k1::scalar::Scalar;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// Set to a file path to export a backup bundle there instead of running the blueprint
+pub const BACKUP_EXPORT_ENV: &str = "WSTS_BACKUP_EXPORT";
+
+/// Set to the path of a backup bundle to restore it instead of running the blueprint
+pub const BACKUP_RESTORE_ENV: &str = "WSTS_BACKUP_RESTORE";
+
+/// The passphrase a backup bundle is encrypted with. Required to export or restore
+pub const BACKUP_PASSPHRASE_ENV: &str = "WSTS_BACKUP_PASSPHRASE";
+
+/// Comma-separated [`KeyId`]s to export, e.g. `1/4/0,1/9/0`. Every key is exported if unset
+pub const BACKUP_KEYS_ENV: &str = "WSTS_BACKUP_KEYS";
+
+/// The hex-encoded script tree root that restored keys were generated with, for keys whose
+/// keygen job output a Taproot key with a script tree
+pub const BACKUP_TAPROOT_MERKLE_ROOT_ENV: &str = "WSTS_BACKUP_TAPROOT_MERKLE_ROOT";
+
+/// The version of the [`BackupBundle`] format written by this build
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Domain separator for the authenticated data of a [`BackupBundle`]
+const BACKUP_SALT: &str = "wsts-backup";
+
+/// Error type for backup export and restore
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("Failed to serialize data: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    StoreError(#[from] StoreError),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("Context error: {0}")]
+    ContextError(String),
+
+    #[error("Invalid backup bundle: {0}")]
+    InvalidBundle(String),
+
+    /// The bundle is intact, but a key in it is not the one the network expects
+    #[error("Backup of key {key} does not match the network: {reason}")]
+    Mismatch { key: String, reason: String },
+}
+
+/// An encrypted, integrity-protected export of key shares. The shares are encrypted with a
+/// key derived from a passphrase, so a bundle can be restored on a fresh machine with a new
+/// operator store key
+///
+/// Preprocessed nonces are never part of a bundle: a restored copy of a nonce that the
+/// original store has since used would sign a second message with it, leaking the share
+#[derive(Serialize, Deserialize)]
+pub struct BackupBundle {
+    pub version: u32,
+    /// The Argon2id salt for the passphrase
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    /// The encrypted [`BackupContents`]
+    pub ciphertext: Vec<u8>,
+}
+
+/// The plaintext of a [`BackupBundle`]
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    /// Each key's state in its [`schema`] envelope, by store key
+    entries: BTreeMap<String, serde_json::Value>,
+}
+
+impl BackupBundle {
+    /// Encrypts `entries`, keyed by store key, with `passphrase`, leaving out their
+    /// preprocessed nonces
+    pub fn seal(
+        entries: &BTreeMap<String, WstsState>,
+        passphrase: &str,
+    ) -> Result<Self, BackupError> {
+        let entries = entries
+            .iter()
+            .map(|(store_key, state)| {
+                let state = WstsState {
+                    nonce_pool: NoncePool::default(),
+                    ..state.clone()
+                };
+                let encoded = schema::encode_state(&state).map_err(StoreError::from)?;
+                Ok((store_key.clone(), serde_json::from_slice(&encoded)?))
+            })
+            .collect::<Result<_, BackupError>>()?;
+        let plaintext = serde_json::to_vec(&BackupContents { entries })?;
+
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 24];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+        let cipher = Self::cipher(passphrase, &salt)?;
+        let aad = Self::aad(BACKUP_FORMAT_VERSION);
+        let payload = Payload {
+            msg: &plaintext,
+            aad: &aad,
+        };
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| BackupError::EncryptionError("Failed to encrypt backup".into()))?;
+
+        Ok(Self {
+            version: BACKUP_FORMAT_VERSION,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the bundle with `passphrase`, migrating every key to the current schema
+    /// version. Any preprocessed nonces in the bundle are dropped. Fails if the passphrase is
+    /// wrong or the bundle was tampered with
+    pub fn open(&self, passphrase: &str) -> Result<BTreeMap<String, WstsState>, BackupError> {
+        if self.version != BACKUP_FORMAT_VERSION {
+            return Err(BackupError::InvalidBundle(format!(
+                "unsupported version {}",
+                self.version
+            )));
+        }
+        if self.nonce.len() != 24 {
+            return Err(BackupError::InvalidBundle("invalid nonce".into()));
+        }
+
+        let cipher = Self::cipher(passphrase, &self.salt)?;
+        let aad = Self::aad(self.version);
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: &aad,
+        };
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(&self.nonce), payload)
+            .map_err(|_| {
+                BackupError::EncryptionError(
+                    "Failed to decrypt backup, wrong passphrase or corrupted bundle".into(),
+                )
+            })?;
+
+        let contents: BackupContents = serde_json::from_slice(&plaintext)?;
+        contents
+            .entries
+            .into_iter()
+            .map(|(store_key, envelope)| {
+                let (mut state, _) = schema::decode_state(&serde_json::to_vec(&envelope)?)
+                    .map_err(StoreError::from)?;
+                state.nonce_pool = NoncePool::default();
+                Ok((store_key, state))
+            })
+            .collect()
+    }
+
+    fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, BackupError> {
+        let mut key = [0u8; 32];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| {
+                BackupError::EncryptionError(format!("Failed to derive the backup key: {err}"))
+            })?;
+
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+
+    fn aad(version: u32) -> Vec<u8> {
+        [BACKUP_SALT.as_bytes(), &version.to_be_bytes()].concat()
+    }
+}
+
+/// An admin action on the key store, run by the binary in place of the blueprint
+pub enum BackupCommand {
+    /// Exports the given keys, or every key, to a bundle at `path`
+    Export {
+        path: PathBuf,
+        keys: Option<Vec<String>>,
+        passphrase: String,
+    },
+    /// Restores every key in the bundle at `path`
+    Restore {
+        path: PathBuf,
+        passphrase: String,
+        taproot_merkle_root: Option<[u8; 32]>,
+    },
+}
+
+impl BackupCommand {
+    /// Reads the command from [`BACKUP_EXPORT_ENV`] or [`BACKUP_RESTORE_ENV`], returning `None`
+    /// if neither is set
+    pub fn from_env() -> Result<Option<Self>, BackupError> {
+        let export = std::env::var(BACKUP_EXPORT_ENV).ok();
+        let restore = std::env::var(BACKUP_RESTORE_ENV).ok();
+        if export.is_none() && restore.is_none() {
+            return Ok(None);
+        }
+
+        let passphrase = std::env::var(BACKUP_PASSPHRASE_ENV).map_err(|_| {
+            BackupError::ContextError(format!("{BACKUP_PASSPHRASE_ENV} must be set"))
+        })?;
+
+        match (export, restore) {
+            (Some(path), None) => {
+                let keys = std::env::var(BACKUP_KEYS_ENV)
+                    .ok()
+                    .map(|keys| keys.split(',').map(|key| key.trim().to_string()).collect());
+
+                Ok(Some(BackupCommand::Export {
+                    path: path.into(),
+                    keys,
+                    passphrase,
+                }))
+            }
+            (None, Some(path)) => {
+                let taproot_merkle_root = std::env::var(BACKUP_TAPROOT_MERKLE_ROOT_ENV)
+                    .ok()
+                    .map(|root| {
+                        let mut bytes = [0u8; 32];
+                        hex::decode_to_slice(&root, &mut bytes).map_err(|err| {
+                            BackupError::ContextError(format!(
+                                "Invalid {BACKUP_TAPROOT_MERKLE_ROOT_ENV}: {err}"
+                            ))
+                        })?;
+                        Ok::<_, BackupError>(bytes)
+                    })
+                    .transpose()?;
+
+                Ok(Some(BackupCommand::Restore {
+                    path: path.into(),
+                    passphrase,
+                    taproot_merkle_root,
+                }))
+            }
+            _ => Err(BackupError::ContextError(format!(
+                "Only one of {BACKUP_EXPORT_ENV} and {BACKUP_RESTORE_ENV} may be set"
+            ))),
+        }
+    }
+
+    pub async fn run(&self, context: &StoreContext) -> Result<(), BackupError> {
+        match self {
+            BackupCommand::Export {
+                path,
+                keys,
+                passphrase,
+            } => {
+                let bundle = export_backup(context, keys.as_deref(), passphrase)?;
+                std::fs::write(path, serde_json::to_vec(&bundle)?)?;
+                info!("Exported key backup to {}", path.display());
+            }
+            BackupCommand::Restore {
+                path,
+                passphrase,
+                taproot_merkle_root,
+            } => {
+                let bundle: BackupBundle = serde_json::from_slice(&std::fs::read(path)?)?;
+                let restored =
+                    restore_backup(context, &bundle, passphrase, *taproot_merkle_root).await?;
+                info!("Restored {restored} key(s) from {}", path.display());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Exports the keys with the given store keys, or every stored key, as a bundle encrypted
+/// with `passphrase`
+pub fn export_backup(
+    context: &StoreContext,
+    keys: Option<&[String]>,
+    passphrase: &str,
+) -> Result<BackupBundle, BackupError> {
+    let keys = match keys {
+        Some(keys) => keys.to_vec(),
+        None => context.store.keys()?,
+    };
+
+    let mut entries = BTreeMap::new();
+    for store_key in keys {
+        let state = context
+            .store
+            .get(&store_key)?
+            .ok_or_else(|| BackupError::ContextError(format!("Key entry {store_key} not found")))?;
+        entries.insert(store_key, state);
+    }
+
+    BackupBundle::seal(&entries, passphrase)
+}
+
+/// Restores every key in `bundle`, returning how many were restored. The whole bundle is
+/// checked against the network before any key is written, and keys that are already in the
+/// store are left untouched
+///
+/// # Errors
+/// Returns an error if the bundle cannot be decrypted, or if any key in it:
+/// - Belongs to another service, or to another party than this operator
+/// - Has polynomial commitments that are invalid, or that our shares do not match
+/// - Has a group key that differs from the one its keygen job published on chain
+pub async fn restore_backup(
+    context: &StoreContext,
+    bundle: &BackupBundle,
+    passphrase: &str,
+    taproot_merkle_root: Option<[u8; 32]>,
+) -> Result<usize, BackupError> {
+    let entries = bundle.open(passphrase)?;
+
+    for (store_key, state) in &entries {
+        validate_restored_key(context, store_key, state, taproot_merkle_root).await?;
+    }
+
+    let mut restored = 0;
+    for (store_key, state) in &entries {
+        if context.store.get(store_key)?.is_some() {
+            warn!("Key {store_key} is already in the store, not restoring it");
+            continue;
+        }
+
+        context.store.set(store_key, state)?;
+        restored += 1;
+    }
+
+    Ok(restored)
+}
+
+/// Checks a restored key against what the network expects of this operator's share of it
+async fn validate_restored_key(
+    context: &StoreContext,
+    store_key: &str,
+    state: &WstsState,
+    taproot_merkle_root: Option<[u8; 32]>,
+) -> Result<(), BackupError> {
+    let key_id: KeyId = store_key.parse().map_err(|reason| BackupError::Mismatch {
+        key: store_key.to_string(),
+        reason,
+    })?;
+    let service_id = context
+        .service_id()
+        .map_err(|e| BackupError::ContextError(e.to_string()))?;
+    if key_id.service_id != service_id {
+        return Err(BackupError::Mismatch {
+            key: store_key.to_string(),
+            reason: format!(
+                "key belongs to service {}, not {service_id}",
+                key_id.service_id
+            ),
+        });
+    }
+
+    let client = context
+        .tangle_client()
+        .await
+        .map_err(|e| BackupError::ContextError(e.to_string()))?;
+    let (i, _) = client
+        .get_party_index_and_operators()
+        .await
+        .map_err(|e| BackupError::ContextError(e.to_string()))?;
+    let published = published_public_key(context, &key_id).await?;
+
+    verify_restored_share(&key_id, state, i as u32, &published, taproot_merkle_root)
+}
+
+/// Checks that `state` is the share of `party_id` in the key `key_id`, whose keygen job
+/// returned `published_key` in any of the output formats
+pub fn verify_restored_share(
+    key_id: &KeyId,
+    state: &WstsState,
+    party_id: u32,
+    published_key: &[u8],
+    taproot_merkle_root: Option<[u8; 32]>,
+) -> Result<(), BackupError> {
+    let mismatch = |reason: String| BackupError::Mismatch {
+        key: key_id.to_string(),
+        reason,
+    };
+
+    // The share must be this operator's
+    if state.party_id != party_id {
+        return Err(mismatch(format!(
+            "share is for party {}, but this operator is party {party_id}",
+            state.party_id
+        )));
+    }
+
+    // Every commitment must be well formed, and our shares must match them
+    let our_key_ids = state
+        .key_ids
+        .get(&state.party_id)
+        .ok_or_else(|| mismatch("no key ids for this party".into()))?;
+    if state.poly_commitments.is_empty() {
+        return Err(mismatch("no polynomial commitments".into()));
+    }
+    for (dealer, commitment) in &state.poly_commitments {
+        if commitment.id.id != wsts::compute::id(*dealer)
+            || commitment.A.is_empty()
+            || !commitment.verify()
+        {
+            return Err(mismatch(format!("invalid commitment from party {dealer}")));
+        }
+
+        let valid = state
+            .shares
+            .get(dealer)
+            .is_some_and(|shares| verify_shares(shares, our_key_ids, commitment));
+        if !valid {
+            return Err(mismatch(format!(
+                "shares from party {dealer} do not match its commitment"
+            )));
+        }
+    }
+
+    // The commitments must add up to the group key the party signs with
+    let group_key = state
+        .poly_commitments
+        .values()
+        .fold(Point::new(), |acc, commitment| acc + commitment.A[0]);
+    let party = state
+        .party_state()
+        .ok_or_else(|| mismatch("no saved party".into()))?;
+    if party.group_key != group_key
+        || state.public_key_frost_format != group_key.compress().data.to_vec()
+    {
+        return Err(mismatch(
+            "group key does not match the polynomial commitments".into(),
+        ));
+    }
+
+    // The private keys the party signs with must be the sums of the verified shares, or the
+    // commitments say nothing about them
+    let party_key_ids = party.key_ids.iter().copied().collect::<BTreeSet<_>>();
+    if party.party_id != state.party_id
+        || party_key_ids != our_key_ids.iter().copied().collect()
+        || party.private_keys.len() != party_key_ids.len()
+    {
+        return Err(mismatch(
+            "saved party does not hold this party's key ids".into(),
+        ));
+    }
+    for key_id in our_key_ids {
+        let expected = state
+            .poly_commitments
+            .keys()
+            .fold(Scalar::from(0), |acc, dealer| {
+                acc + state.shares[dealer][key_id]
+            });
+        if party.private_keys.get(key_id) != Some(&expected) {
+            return Err(mismatch(format!(
+                "private key for key id {key_id} is not the sum of its shares"
+            )));
+        }
+    }
+
+    // And the group key must be the one the keygen job published
+    let formats = [
+        OutputFormat::Frost,
+        OutputFormat::Bip340,
+        OutputFormat::Taproot(taproot_merkle_root),
+    ];
+    let matches_published = formats.iter().any(|format| {
+        format
+            .encode_public_key(&state.public_key_frost_format)
+            .is_ok_and(|encoded| encoded == published_key)
+    });
+    if !matches_published {
+        return Err(mismatch(format!(
+            "group key does not match the result of keygen job {}",
+            key_id.keygen_call_id
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fetches the public key returned by the keygen job of `key_id` from the chain
+async fn published_public_key(
+    context: &StoreContext,
+    key_id: &KeyId,
+) -> Result<Vec<u8>, BackupError> {
+    let client = context
+        .tangle_client()
+        .await
+        .map_err(|e| BackupError::ContextError(e.to_string()))?;
+
+    let address = api::storage()
+        .services()
+        .job_results(key_id.service_id, key_id.keygen_call_id);
+    let result = client
+        .storage()
+        .at_latest()
+        .await
+        .map_err(|e| BackupError::ContextError(e.to_string()))?
+        .fetch(&address)
+        .await
+        .map_err(|e| BackupError::ContextError(e.to_string()))?
+        .ok_or_else(|| {
+            BackupError::ContextError(format!(
+                "No result on chain for keygen job {}",
+                key_id.keygen_call_id
+            ))
+        })?;
+
+    match result.result.0.first() {
+        Some(Field::List(BoundedVec(values))) => values
+            .iter()
+            .map(|value| match value {
+                Field::Uint8(byte) => Ok(*byte),
+                _ => Err(BackupError::ContextError(
+                    "Keygen result is not a byte list".into(),
+                )),
+            })
+            .collect(),
+        _ => Err(BackupError::ContextError(
+            "Keygen result is not a byte list".into(),
+        )),
+    }
+}
@@ -1,5 +1,14 @@
 use crate::keygen_state_machine::WstsState;
+use crate::policy::PolicyEngine;
+use crate::preprocessing_state_machine::{
+    advance_ledger, NonceSlot, NonceStatus, PreprocessedNonce,
+};
+use crate::signing::SigningError;
+use crate::signing_state_machine::{NonceLedger, PreprocessedNonces};
+use crate::store::{EncryptedStore, KeyId, StoreBackend, StoreCipher, StoreError};
+use crate::transcript::SigningTranscript;
 use blueprint_sdk::config::StdGadgetConfiguration;
+use blueprint_sdk::logging::{error, info};
 use blueprint_sdk::macros::contexts::{
     KeystoreContext, P2pContext, ServicesContext, TangleClientContext,
 };
@@ -7,9 +16,12 @@ use blueprint_sdk::networking::networking::NetworkMultiplexer;
 use blueprint_sdk::networking::setup::start_p2p_network;
 use blueprint_sdk::networking::GossipMsgKeyPair;
 use blueprint_sdk::stores::local_database::LocalDatabase;
+use blueprint_sdk::tangle_subxt::subxt::utils::AccountId32;
 use color_eyre::eyre;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
+use wsts::common::PublicNonce;
 
 /// The network protocol version for the WSTS service
 const NETWORK_PROTOCOL: &str = "/wsts/frost/1.0.0";
@@ -24,36 +36,278 @@ pub struct WstsContext {
     #[call_id]
     pub call_id: Option<u64>,
     pub network_backend: Arc<NetworkMultiplexer>,
-    pub store: Arc<LocalDatabase<WstsState>>,
+    /// The key shares of every key this operator holds, in the backend chosen by
+    /// [`STORE_BACKEND_ENV`](crate::store::STORE_BACKEND_ENV)
+    pub store: Arc<EncryptedStore>,
+    /// The transcript of every message this operator signed, by hex transcript digest
+    pub transcripts: Arc<LocalDatabase<SigningTranscript>>,
     pub identity: GossipMsgKeyPair,
+    pub signing_sessions: Arc<parking_lot::Mutex<HashSet<[u8; 32]>>>,
+    /// The operator's rules for which signing requests to take part in
+    pub policy: Arc<PolicyEngine>,
+    /// The account that called the current job, set by [`caller_pre_processor`]
+    ///
+    /// [`caller_pre_processor`]: crate::policy::caller_pre_processor
+    pub caller: Option<AccountId32>,
 }
 
 // Core context management implementation
 impl WstsContext {
-    /// Creates a new service context with the provided configuration
+    /// Creates a new service context with the provided configuration. Key shares are stored
+    /// encrypted, with a key derived from [`STORE_PASSPHRASE_ENV`] if it is set, or from the
+    /// operator's identity key otherwise. Keys stored by older versions under `hex(meta_hash)`
+    /// are moved to their [`KeyId`] once the store is open
+    ///
+    /// [`STORE_PASSPHRASE_ENV`]: crate::store::STORE_PASSPHRASE_ENV
     ///
     /// # Errors
     /// Returns an error if:
     /// - Network initialization fails
     /// - Configuration is invalid
+    /// - The key store cannot be opened or migrated
     pub fn new(config: StdGadgetConfiguration) -> eyre::Result<Self> {
         let network_config = config
             .libp2p_network_config(NETWORK_PROTOCOL)
             .map_err(|err| eyre::eyre!("Failed to create network configuration: {err}"))?;
 
         let identity = network_config.secret_key.clone();
+        let store = open_store(&config, &identity)?;
+
         let gossip_handle = start_p2p_network(network_config)
             .map_err(|err| eyre::eyre!("Failed to start the P2P network: {err}"))?;
 
-        let keystore_dir = PathBuf::from(config.keystore_uri.clone()).join("wsts.json");
-        let store = Arc::new(LocalDatabase::open(keystore_dir));
+        let keystore_dir = PathBuf::from(config.keystore_uri.clone());
+        let transcripts = Arc::new(LocalDatabase::open(
+            keystore_dir.join("wsts-transcripts.json"),
+        ));
+        let policy = Arc::new(PolicyEngine::load(&keystore_dir)?);
 
         Ok(Self {
             store,
+            transcripts,
             call_id: None,
             identity,
             config,
             network_backend: Arc::new(NetworkMultiplexer::new(gossip_handle)),
+            signing_sessions: Arc::new(parking_lot::Mutex::new(HashSet::new())),
+            policy,
+            caller: None,
+        })
+    }
+
+    /// Returns the id of the service instance this context runs for
+    pub fn service_id(&self) -> eyre::Result<u64> {
+        service_id(&self.config)
+    }
+
+    /// Returns the id of the key generated by the given keygen job of this service
+    pub fn key_id(&self, keygen_call_id: u64) -> eyre::Result<KeyId> {
+        Ok(KeyId::new(self.service_id()?, keygen_call_id))
+    }
+
+    /// Applies `update` to the stored state of a key, returning `None` if there is no such key.
+    /// `update` may run more than once if the entry is updated concurrently
+    pub fn update_key_state<T>(
+        &self,
+        store_key: &str,
+        update: impl FnMut(&mut WstsState) -> T,
+    ) -> Result<Option<T>, StoreError> {
+        self.store.update(store_key, update)
+    }
+
+    /// Returns the ledger that persists the nonce lifecycle of a signing session, in a record
+    /// of its own next to its key
+    pub fn nonce_ledger(&self, store_key: &str, session_id: [u8; 32]) -> StoreNonceLedger {
+        StoreNonceLedger {
+            store: self.store.clone(),
+            ledger_key: crate::store::nonce_ledger_key(store_key, &hex::encode(session_id)),
+        }
+    }
+
+    /// Returns the preprocessed nonces in the stored state of a key
+    pub fn nonce_pool(&self, store_key: &str) -> StoreNoncePool {
+        StoreNoncePool {
+            context: self.clone(),
+            store_key: store_key.to_string(),
+        }
+    }
+
+    /// Marks the signing session with the given execution id as running until the returned
+    /// guard is dropped
+    ///
+    /// Returns `None` if a session with the same execution id is already running, since the
+    /// two would receive each other's messages
+    pub fn start_signing_session(&self, execution_id: [u8; 32]) -> Option<SigningSessionGuard> {
+        if !self.signing_sessions.lock().insert(execution_id) {
+            return None;
+        }
+
+        Some(SigningSessionGuard {
+            execution_id,
+            sessions: self.signing_sessions.clone(),
+        })
+    }
+}
+
+/// The part of the [`WstsContext`] that admin actions on the key store need. Unlike a
+/// [`WstsContext`], it does not start the P2P network, so keys can be backed up and restored
+/// without the operator joining the network
+#[derive(Clone, TangleClientContext)]
+pub struct StoreContext {
+    #[config]
+    pub config: StdGadgetConfiguration,
+    /// The key shares of every key this operator holds
+    pub store: Arc<EncryptedStore>,
+}
+
+impl StoreContext {
+    /// Opens the key store the way [`WstsContext::new`] does, including moving legacy keys to
+    /// their key ids
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid, or the key store cannot be opened or
+    /// migrated
+    pub fn new(config: StdGadgetConfiguration) -> eyre::Result<Self> {
+        let identity = config
+            .libp2p_network_config(NETWORK_PROTOCOL)
+            .map_err(|err| eyre::eyre!("Failed to create network configuration: {err}"))?
+            .secret_key;
+        let store = open_store(&config, &identity)?;
+
+        Ok(Self { config, store })
+    }
+
+    /// Returns the id of the service instance this context runs for
+    pub fn service_id(&self) -> eyre::Result<u64> {
+        service_id(&self.config)
+    }
+}
+
+/// Opens the operator's encrypted key store in its keystore directory, moving any keys stored
+/// by older versions under `hex(meta_hash)` to their [`KeyId`]
+fn open_store(
+    config: &StdGadgetConfiguration,
+    identity: &GossipMsgKeyPair,
+) -> eyre::Result<Arc<EncryptedStore>> {
+    let keystore_dir = PathBuf::from(config.keystore_uri.clone());
+    let cipher = StoreCipher::from_env(identity)?;
+    let store = Arc::new(EncryptedStore::open(
+        &keystore_dir,
+        StoreBackend::from_env()?,
+        cipher,
+    )?);
+
+    let settings = config
+        .protocol_settings
+        .tangle()
+        .map_err(|err| eyre::eyre!("Failed to read the Tangle settings: {err}"))?;
+    if let Some(service_id) = settings.service_id {
+        let migrated = store.migrate_legacy_keys(service_id, settings.blueprint_id)?;
+        if migrated > 0 {
+            info!("Moved {migrated} legacy key(s) to their key ids");
+        }
+    }
+
+    Ok(store)
+}
+
+fn service_id(config: &StdGadgetConfiguration) -> eyre::Result<u64> {
+    config
+        .protocol_settings
+        .tangle()
+        .map_err(|err| eyre::eyre!("Failed to read the Tangle settings: {err}"))?
+        .service_id
+        .ok_or_else(|| eyre::eyre!("service_id not set"))
+}
+
+/// Keeps a signing session registered as running in the [`WstsContext`] while alive
+pub struct SigningSessionGuard {
+    execution_id: [u8; 32],
+    sessions: Arc<parking_lot::Mutex<HashSet<[u8; 32]>>>,
+}
+
+impl Drop for SigningSessionGuard {
+    fn drop(&mut self) {
+        self.sessions.lock().remove(&self.execution_id);
+    }
+}
+
+/// A [`NonceLedger`] kept in the key store, with one record per session
+pub struct StoreNonceLedger {
+    store: Arc<EncryptedStore>,
+    ledger_key: String,
+}
+
+impl StoreNonceLedger {
+    /// Drops the session's ledger once the session is over, since its secret nonces are gone
+    /// by then. Only a crash leaves a ledger behind, which keeps a rerun of the session from
+    /// signing with different nonces until it expires
+    pub fn clear(&self) {
+        if let Err(err) = self.store.delete(&self.ledger_key) {
+            error!("Failed to clear nonce ledger {}: {err}", self.ledger_key);
+        }
+    }
+}
+
+impl NonceLedger for StoreNonceLedger {
+    fn advance(
+        &self,
+        public_nonces: &[PublicNonce],
+        status: NonceStatus,
+    ) -> Result<(), SigningError> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| SigningError::ContextError(e.to_string()))?
+            .as_secs();
+        self.store
+            .update_nonce_ledger(&self.ledger_key, |records| {
+                advance_ledger(records, public_nonces, status, now)
+            })
+            .map_err(|e| SigningError::ContextError(e.to_string()))?
+    }
+}
+
+/// [`PreprocessedNonces`] backed by the `nonce_pool` of a key's stored state. Nonces are
+/// removed from the store before they are returned, so they can never be handed out twice
+pub struct StoreNoncePool {
+    context: WstsContext,
+    store_key: String,
+}
+
+impl StoreNoncePool {
+    fn take(
+        &self,
+        take: impl Fn(&mut WstsState) -> Option<Vec<PreprocessedNonce>>,
+    ) -> Option<Vec<PreprocessedNonce>> {
+        let taken = self.context.update_key_state(&self.store_key, |state| {
+            let nonces = take(state);
+            state.nonce_pool.prune();
+            nonces
+        });
+
+        taken
+            .unwrap_or_else(|err| {
+                error!("Failed to take preprocessed nonces: {err}");
+                None
+            })
+            .flatten()
+    }
+}
+
+impl PreprocessedNonces for StoreNoncePool {
+    fn take_next(&self, count: usize) -> Vec<PreprocessedNonce> {
+        // Signing falls back to online nonces, so a store failure only costs a round trip
+        self.take(|state| {
+            if state.nonce_pool.remaining() < count {
+                return None;
+            }
+
+            Some((0..count).filter_map(|_| state.nonce_pool.take()).collect())
         })
+        .unwrap_or_default()
+    }
+
+    fn take_slots(&self, slots: &[NonceSlot]) -> Option<Vec<PreprocessedNonce>> {
+        self.take(|state| state.nonce_pool.take_slots(slots))
     }
 }
@@ -1,5 +1,6 @@
 use crate::keygen_state_machine;
-use crate::utils::validate_parameters;
+use crate::store::KeyId;
+use crate::utils::{allocate_party_key_ids, OutputFormat};
 use crate::{context::WstsContext, keygen_state_machine::WstsState};
 use blueprint_sdk::crypto::k256::K256VerifyingKey;
 use blueprint_sdk::crypto::KeyEncoding;
@@ -11,14 +12,16 @@ use blueprint_sdk::job;
 use blueprint_sdk::logging::info;
 use blueprint_sdk::macros::ext::contexts::tangle::TangleClientContext;
 use blueprint_sdk::networking::round_based_compat::NetworkDeliveryWrapper;
+use blueprint_sdk::networking::GossipMsgKeyPair;
 use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::services::events::JobCalled;
 use gadget_macros::ext::clients::GadgetServicesClient;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use wsts::v2::Party;
 
 #[job(
     id = 0,
-    params(t),
+    params(t, k, weights, output_format, taproot_merkle_root),
     event_listener(
         listener = TangleEventListener<WstsContext, JobCalled>,
         pre_processor = services_pre_processor,
@@ -28,7 +31,16 @@ use wsts::v2::Party;
 /// Runs a distributed key generation (DKG) process using the WSTS protocol
 ///
 /// # Arguments
-/// * `t` - The threshold for the DKG
+/// * `t` - The threshold for the DKG, in key ids
+/// * `k` - The total number of key ids. If 0, it is derived from `weights`, or is the number of
+///   operators when `weights` is empty
+/// * `weights` - The number of key ids to give each operator, in operator order. If empty,
+///   the `k` key ids are split as evenly as possible between the operators. Weights are only
+///   ever taken from this parameter, never from the operators' stake on chain
+/// * `output_format` - How to encode the public key: 0 for a 33-byte compressed key, 1 for a
+///   32-byte BIP340 x-only key, 2 for the 32-byte x-only BIP341 Taproot output key
+/// * `taproot_merkle_root` - The script merkle root to tweak the Taproot output key with, or
+///   empty for a key-path-only output. Must be empty unless `output_format` is 2
 /// * `context` - The DFNS context containing network and storage configuration
 ///
 /// # Returns
@@ -40,7 +52,17 @@ use wsts::v2::Party;
 /// - Failed to get party information
 /// - MPC protocol execution failed
 /// - Serialization of results failed
-pub async fn keygen(t: u16, context: WstsContext) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+pub async fn keygen(
+    t: u16,
+    k: u16,
+    weights: Vec<u16>,
+    output_format: u8,
+    taproot_merkle_root: Vec<u8>,
+    context: WstsContext,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let format =
+        OutputFormat::new(output_format, &taproot_merkle_root).map_err(KeygenError::SetupError)?;
+
     // Get configuration and compute deterministic values
     let client = context.tangle_client().await?;
     let blueprint_id = client
@@ -50,6 +72,9 @@ pub async fn keygen(t: u16, context: WstsContext) -> Result<Vec<u8>, Box<dyn std
     let call_id = context
         .call_id
         .ok_or_else(|| KeygenError::ContextError("Call_id not set".into()))?;
+    let service_id = context
+        .service_id()
+        .map_err(|e| KeygenError::ContextError(e.to_string()))?;
 
     // Setup party information
     let (i, operators) = client
@@ -70,13 +95,14 @@ pub async fn keygen(t: u16, context: WstsContext) -> Result<Vec<u8>, Box<dyn std
 
     let n = parties.len() as u16;
     let i = i as u16;
-    let k = n;
+    let key_ids = allocate_party_key_ids(n as _, k as _, t as _, &weights)?;
+    let k: usize = key_ids.iter().map(Vec::len).sum();
 
-    let (meta_hash, deterministic_hash) =
+    let (_, deterministic_hash) =
         crate::compute_execution_hashes(n, blueprint_id, call_id, KEYGEN_SALT);
 
     info!(
-        "Starting WSTS Keygen for party {i}, n={n}, eid={}",
+        "Starting WSTS Keygen for party {i}, n={n}, k={k}, eid={}",
         hex::encode(deterministic_hash)
     );
 
@@ -87,7 +113,17 @@ pub async fn keygen(t: u16, context: WstsContext) -> Result<Vec<u8>, Box<dyn std
         parties.clone(),
     );
 
-    let state = protocol(n as _, i as _, k as _, t as _, network).await?;
+    let state = protocol(
+        n as _,
+        i as _,
+        t as _,
+        key_ids,
+        deterministic_hash,
+        &context.identity,
+        &parties,
+        network,
+    )
+    .await?;
 
     info!(
         "Ending WSTS Keygen for party {i}, n={n}, eid={}",
@@ -96,10 +132,16 @@ pub async fn keygen(t: u16, context: WstsContext) -> Result<Vec<u8>, Box<dyn std
 
     let public_key_frost_format = state.public_key_frost_format.clone();
     // Store the results
-    let store_key = hex::encode(meta_hash);
-    context.store.set(&store_key, state);
+    let store_key = KeyId::new(service_id, call_id).store_key();
+    context
+        .store
+        .set(&store_key, &state)
+        .map_err(|e| KeygenError::ContextError(e.to_string()))?;
 
-    Ok(public_key_frost_format)
+    let public_key = format
+        .encode_public_key(&public_key_frost_format)
+        .map_err(KeygenError::SerializationError)?;
+    Ok(public_key)
 }
 
 /// Configuration constants for the WSTS keygen process
@@ -122,26 +164,107 @@ pub enum KeygenError {
 
     #[error("Setup error: {0}")]
     SetupError(String),
+
+    /// The protocol aborted because of the misbehaviour of the listed parties. The error
+    /// message is a JSON [`BlameReport`] so it can be parsed from the job's failure result
+    #[error("Keygen aborted: {}", BlameReport::json(culprits, *reason))]
+    Blame {
+        culprits: Vec<u32>,
+        reason: BlameReason,
+    },
+}
+
+impl KeygenError {
+    /// Returns the parties blamed for the failure, if the failure is attributable
+    pub fn culprits(&self) -> Option<&[u32]> {
+        match self {
+            KeygenError::Blame { culprits, .. } => Some(culprits),
+            _ => None,
+        }
+    }
+}
+
+/// Why a set of parties was blamed for aborting keygen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlameReason {
+    /// The party sent private shares that do not match its polynomial commitment
+    InvalidShares,
+    /// The party's polynomial commitment is malformed
+    InvalidCommitment,
+    /// The Schnorr ID proof in the party's polynomial commitment does not verify
+    InvalidIdProof,
+    /// The party's polynomial commitment was not received identically by every party
+    Equivocation,
+    /// The party sent a message in the name of another party, or addressed to another party
+    Impersonation,
+    /// The party's polynomial commitment is not signed with its operator identity key
+    InvalidSignature,
+    /// The party echoed a commitment digest that the dealer never signed
+    InvalidEcho,
+}
+
+impl std::fmt::Display for BlameReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlameReason::InvalidShares => write!(f, "invalid shares"),
+            BlameReason::InvalidCommitment => write!(f, "invalid commitment"),
+            BlameReason::InvalidIdProof => write!(f, "invalid ID proof"),
+            BlameReason::Equivocation => write!(f, "equivocation"),
+            BlameReason::Impersonation => write!(f, "impersonation"),
+            BlameReason::InvalidSignature => write!(f, "invalid signature"),
+            BlameReason::InvalidEcho => write!(f, "invalid echo"),
+        }
+    }
+}
+
+/// The machine-readable form of a [`KeygenError::Blame`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlameReport {
+    pub culprits: Vec<u32>,
+    pub reason: BlameReason,
+}
+
+impl BlameReport {
+    fn json(culprits: &[u32], reason: BlameReason) -> String {
+        let report = BlameReport {
+            culprits: culprits.to_vec(),
+            reason,
+        };
+        serde_json::to_string(&report).unwrap_or_else(|_| format!("{reason}: {culprits:?}"))
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn protocol(
     n: u32,
     party_id: u32,
-    k: u32,
     t: u32,
+    key_ids: Vec<Vec<u32>>,
+    deterministic_hash: [u8; 32],
+    identity: &GossipMsgKeyPair,
+    parties: &BTreeMap<u16, K256VerifyingKey>,
     network: NetworkDeliveryWrapper<keygen_state_machine::Msg>,
 ) -> Result<WstsState, KeygenError> {
-    validate_parameters(n, k, t)?;
     let mut rng = rand::rngs::OsRng;
-    let key_ids = crate::utils::generate_party_key_ids(n, k);
+    let k = key_ids.iter().map(Vec::len).sum::<usize>() as u32;
     let our_key_ids = key_ids
         .get(party_id as usize)
         .ok_or_else(|| KeygenError::ContextError("Bad party_id".to_string()))?;
 
     let network = round_based::party::MpcParty::connected(network);
     let mut party = Party::new(party_id, our_key_ids, n, k, t, &mut rng);
-    let state =
-        keygen_state_machine::wsts_protocol(network, &mut party, n as usize, &mut rng).await?;
+    let state = keygen_state_machine::wsts_protocol(
+        network,
+        &mut party,
+        &key_ids,
+        n as usize,
+        deterministic_hash,
+        identity,
+        parties,
+        &mut rng,
+    )
+    .await?;
 
     info!(
         "Combined public key: {:?}",
@@ -1,21 +1,36 @@
 use rand::{CryptoRng, RngCore};
 use round_based::{
-    rounds_router::{simple_store::RoundInput, RoundsRouter},
+    rounds_router::{
+        simple_store::{RoundInput, RoundMsgs},
+        RoundsRouter,
+    },
     Delivery, MessageDestination, Mpc, MpcParty, ProtocolMessage,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use crate::keygen::KeygenError;
-use blueprint_sdk::logging::{info, trace};
+use crate::keygen::{BlameReason, KeygenError};
+use crate::preprocessing_state_machine::NoncePool;
+use blueprint_sdk::crypto::k256::{K256Ecdsa, K256Signature, K256VerifyingKey};
+use blueprint_sdk::crypto::KeyType;
+use blueprint_sdk::logging::{info, trace, warn};
+use blueprint_sdk::networking::GossipMsgKeyPair;
 use frost_secp256k1_tr::VerifyingKey;
 use itertools::Itertools;
+use k256::sha2::{Digest, Sha256};
+use p256k1::point::{Point, G};
 use round_based::SinkExt;
 use std::sync::Arc;
 use wsts::common::PolyCommitment;
 use wsts::v2::{Party, PartyState};
 use wsts::Scalar;
 
+/// Domain separator for a dealer's signature over its [`KeygenMsg`]
+const BROADCAST_SIGNATURE_SALT: &str = "wsts-keygen-broadcast";
+
+/// A party's share of a generated key, as kept in the key store. Its serialized layout is
+/// versioned by [`crate::schema`], so any change to it, including to the `wsts` types it holds,
+/// needs a migration there
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct WstsState {
     pub party_id: u32,
@@ -25,6 +40,7 @@ pub struct WstsState {
     pub n_signers: usize,
     pub party: Arc<parking_lot::Mutex<Option<PartyState>>>,
     pub public_key_frost_format: Vec<u8>,
+    pub nonce_pool: NoncePool,
 }
 
 impl WstsState {
@@ -35,25 +51,128 @@ impl WstsState {
             ..Default::default()
         }
     }
+
+    /// Returns a copy of the saved party, so that each signing session works on its own
+    /// party and nonce rather than on state shared with other sessions for the same key
+    pub fn party_state(&self) -> Option<PartyState> {
+        self.party
+            .lock()
+            .as_ref()
+            .map(|state| Party::load(state).save())
+    }
 }
 
 #[derive(ProtocolMessage, Serialize, Deserialize, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Msg {
     KeygenBroadcast(KeygenMsg),
+    KeygenShares(KeygenSharesMsg),
+    KeygenEcho(KeygenEchoMsg),
+    KeygenComplaint(KeygenComplaintMsg),
+    KeygenJustification(KeygenJustificationMsg),
 }
 
+/// The public part of a party's keygen contribution, broadcast to everyone
 #[derive(Serialize, Deserialize, Clone)]
 pub struct KeygenMsg {
     source: u32,
-    shares: HashMap<u32, Scalar>,
     key_ids: Vec<u32>,
     poly_commitment: PolyCommitment,
+    session_proof: SessionIdProof,
+    /// The dealer's signature over its [`commitment_digest`] with its operator identity key,
+    /// so that a dealer that sends different commitments to different parties can be proven
+    /// to have done so
+    signature: K256Signature,
+}
+
+/// A Schnorr proof of knowledge of the constant term of a dealer's polynomial, bound to the
+/// dealer's party id and the keygen session. The ID proof inside a [`PolyCommitment`] does not
+/// commit to the session, so on its own it could be replayed from another keygen
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionIdProof {
+    nonce_commitment: Point,
+    response: Scalar,
+}
+
+impl SessionIdProof {
+    fn new<R: CryptoRng + RngCore>(
+        ctx: &[u8; 32],
+        party_id: u32,
+        secret: &Scalar,
+        rng: &mut R,
+    ) -> Self {
+        let nonce = Scalar::random(rng);
+        let nonce_commitment = nonce * G;
+        let challenge = Self::challenge(ctx, party_id, &(*secret * G), &nonce_commitment);
+
+        SessionIdProof {
+            nonce_commitment,
+            response: nonce + challenge * *secret,
+        }
+    }
+
+    fn verify(&self, ctx: &[u8; 32], party_id: u32, public: &Point) -> bool {
+        let challenge = Self::challenge(ctx, party_id, public, &self.nonce_commitment);
+        self.response * G == self.nonce_commitment + challenge * *public
+    }
+
+    fn challenge(
+        ctx: &[u8; 32],
+        party_id: u32,
+        public: &Point,
+        nonce_commitment: &Point,
+    ) -> Scalar {
+        Scalar::from(crate::compute_sha256_hash!(
+            ctx,
+            party_id.to_be_bytes(),
+            public.compress().data,
+            nonce_commitment.compress().data
+        ))
+    }
+}
+
+/// The secret polynomial evaluations for the key ids owned by `destination`.
+/// These are only ever sent P2P to the owner of the key ids
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeygenSharesMsg {
+    source: u32,
+    destination: u32,
+    shares: HashMap<u32, Scalar>,
+}
+
+/// The digest of every dealer's [`KeygenMsg`] as received by `source`, along with the
+/// dealer's signature over it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeygenEchoMsg {
+    source: u32,
+    digests: BTreeMap<u32, ([u8; 32], K256Signature)>,
+}
+
+/// The dealers whose shares to `source` failed verification. Every party sends one, even if
+/// `accused` is empty, so that all parties agree on the full set of complaints
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeygenComplaintMsg {
+    source: u32,
+    accused: Vec<u32>,
+}
+
+/// A dealer's answer to the complaints against it: the shares it sent to each complainer,
+/// revealed so every party can check them against the dealer's commitment
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeygenJustificationMsg {
+    source: u32,
+    shares: HashMap<u32, HashMap<u32, Scalar>>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn wsts_protocol<M, R: CryptoRng + RngCore>(
     network: M,
     signer: &mut Party,
+    party_key_ids: &[Vec<u32>],
     n_signers: usize,
+    ctx: [u8; 32],
+    identity: &GossipMsgKeyPair,
+    parties: &BTreeMap<u16, K256VerifyingKey>,
     rng: &mut R,
 ) -> Result<WstsState, KeygenError>
 where
@@ -68,71 +187,377 @@ where
         state.party_id as _,
         n_signers as _,
     ));
+    let round2 = rounds.add_round(RoundInput::<KeygenSharesMsg>::p2p(
+        state.party_id as _,
+        n_signers as _,
+    ));
+    let round_echo = rounds.add_round(RoundInput::<KeygenEchoMsg>::broadcast(
+        state.party_id as _,
+        n_signers as _,
+    ));
+    let round3 = rounds.add_round(RoundInput::<KeygenComplaintMsg>::broadcast(
+        state.party_id as _,
+        n_signers as _,
+    ));
+    let round4 = rounds.add_round(RoundInput::<KeygenJustificationMsg>::broadcast(
+        state.party_id as _,
+        n_signers as _,
+    ));
     let mut rounds = rounds.listen(incomings);
-    // Broadcast our keygen data
+    // Broadcast our public keygen data
     let shares: HashMap<u32, Scalar> = signer.get_shares().into_iter().collect();
     let key_ids = signer.key_ids.clone();
     let poly_commitment = signer.get_poly_commitment(rng);
+    let session_proof = SessionIdProof::new(
+        &ctx,
+        signer.party_id,
+        &signer.save().polynomial.data()[0],
+        rng,
+    );
+
+    let my_digest = digest_commitment(signer.party_id, &key_ids, &poly_commitment);
+    let signature = K256Ecdsa::sign_with_secret(
+        &mut identity.clone(),
+        &broadcast_signing_digest(&ctx, &my_digest),
+    )
+    .map_err(|err| KeygenError::MpcError(err.to_string()))?;
 
     let my_broadcast = KeygenMsg {
         source: signer.party_id,
-        shares: shares.clone(),
         key_ids: key_ids.clone(),
         poly_commitment: poly_commitment.clone(),
+        session_proof,
+        signature,
     };
     let msg = Msg::KeygenBroadcast(my_broadcast.clone());
 
     send_message::<M, _>(msg, &mut outgoings).await?;
+
+    // Send each party only the shares for the key ids it owns
+    let mut my_shares = None;
+    for (destination, destination_key_ids) in party_key_ids.iter().enumerate() {
+        let destination = destination as u32;
+        let shares_msg = KeygenSharesMsg {
+            source: signer.party_id,
+            destination,
+            shares: destination_key_ids
+                .iter()
+                .filter_map(|key_id| shares.get(key_id).map(|share| (*key_id, *share)))
+                .collect(),
+        };
+
+        if destination == signer.party_id {
+            my_shares = Some(shares_msg);
+        } else {
+            send_message::<M, _>(Msg::KeygenShares(shares_msg), &mut outgoings).await?;
+        }
+    }
+
+    let my_shares =
+        my_shares.ok_or_else(|| KeygenError::SetupError("Party has no key ids".to_string()))?;
+
     let messages = rounds
         .complete(round1)
         .await
         .map_err(|err| KeygenError::MpcError(err.to_string()))?;
 
-    let messages: HashMap<u32, KeygenMsg> = messages
-        .into_iter_including_me(my_broadcast)
-        .map(|r| ((r.source) as _, r))
-        .collect();
+    // Culprits must be the parties that really sent the offending messages, so round 1 is
+    // keyed by sender rather than by the `source` each dealer claims
+    let messages = collect_by_sender(messages, signer.party_id, my_broadcast)?;
+
+    let share_messages = rounds
+        .complete(round2)
+        .await
+        .map_err(|err| KeygenError::MpcError(err.to_string()))?;
+
+    // Every commitment must be signed by its dealer, so that the echoes below can prove
+    // equivocation
+    let unsigned = messages
+        .iter()
+        .filter(|(party_id, msg)| {
+            !verify_broadcast_signature(
+                &ctx,
+                parties,
+                **party_id,
+                &commitment_digest(msg),
+                &msg.signature,
+            )
+        })
+        .map(|(party_id, _)| *party_id)
+        .collect_vec();
+
+    if !unsigned.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: unsigned,
+            reason: BlameReason::InvalidSignature,
+        });
+    }
+
+    // Echo the digest of every commitment we received. A dealer that sent different
+    // commitments to different parties shows up as a digest mismatch, and aborting here keeps
+    // honest parties from ending up with different group keys
+    let my_echo = KeygenEchoMsg {
+        source: signer.party_id,
+        digests: messages
+            .iter()
+            .map(|(party_id, msg)| (*party_id, (commitment_digest(msg), msg.signature.clone())))
+            .collect(),
+    };
+    send_message::<M, _>(Msg::KeygenEcho(my_echo.clone()), &mut outgoings).await?;
+
+    let echoes = rounds
+        .complete(round_echo)
+        .await
+        .map_err(|err| KeygenError::MpcError(err.to_string()))?;
+    let echoes = collect_by_sender(echoes, signer.party_id, my_echo.clone())?;
+
+    // A dealer is only blamed for a mismatch if it signed both digests. A digest without a
+    // valid signature from the dealer, or a missing one, is the echoing party's fault
+    let mut equivocators = BTreeSet::new();
+    let mut bad_echoes = BTreeSet::new();
+    for (sender, echo) in &echoes {
+        for (dealer, (digest, _)) in &my_echo.digests {
+            match echo.digests.get(dealer) {
+                Some((echoed, _)) if echoed == digest => {}
+                Some((echoed, signature))
+                    if verify_broadcast_signature(&ctx, parties, *dealer, echoed, signature) =>
+                {
+                    equivocators.insert(*dealer);
+                }
+                _ => {
+                    bad_echoes.insert(*sender);
+                }
+            }
+        }
+    }
+
+    if !equivocators.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: equivocators.into_iter().collect(),
+            reason: BlameReason::Equivocation,
+        });
+    }
+
+    if !bad_echoes.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: bad_echoes.into_iter().collect(),
+            reason: BlameReason::InvalidEcho,
+        });
+    }
+
+    // Every commitment must be for a polynomial of the same degree as ours and the dealer
+    // must claim exactly the key ids assigned to it. This runs before the proofs are checked,
+    // since verifying a commitment reads its constant term, which an empty one does not have
+    let bad_commitments = messages
+        .iter()
+        .filter(|(party_id, msg)| {
+            msg.poly_commitment.A.len() != poly_commitment.A.len()
+                || party_key_ids.get(**party_id as usize) != Some(&msg.key_ids)
+        })
+        .map(|(party_id, _)| *party_id)
+        .sorted()
+        .collect_vec();
+
+    if !bad_commitments.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: bad_commitments,
+            reason: BlameReason::InvalidCommitment,
+        });
+    }
+
+    // Reject any dealer that cannot prove knowledge of its secret for this session, which
+    // would otherwise allow it to pick its commitment as a function of the others'
+    let bad_proofs = messages
+        .iter()
+        .filter(|(party_id, msg)| {
+            let commitment = &msg.poly_commitment;
+            commitment.id.id != wsts::compute::id(**party_id)
+                || !commitment.verify()
+                || !commitment
+                    .A
+                    .first()
+                    .is_some_and(|public| msg.session_proof.verify(&ctx, **party_id, public))
+        })
+        .map(|(party_id, _)| *party_id)
+        .sorted()
+        .collect_vec();
+
+    if !bad_proofs.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: bad_proofs,
+            reason: BlameReason::InvalidIdProof,
+        });
+    }
 
     // Load the state
     for (party_id, msg) in messages {
-        state.shares.insert(party_id, msg.shares);
         state.key_ids.insert(party_id, msg.key_ids);
         state.poly_commitments.insert(party_id, msg.poly_commitment);
     }
 
+    let share_messages = collect_by_sender(share_messages, signer.party_id, my_shares)?;
+    let misdirected = share_messages
+        .iter()
+        .filter(|(_, msg)| msg.destination != signer.party_id)
+        .map(|(sender, _)| *sender)
+        .collect_vec();
+    if !misdirected.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: misdirected,
+            reason: BlameReason::Impersonation,
+        });
+    }
+
+    for (dealer, msg) in share_messages {
+        state.shares.insert(dealer, msg.shares);
+    }
+
     trace!(
         "Received shares: {:?}",
         state.shares.keys().collect::<Vec<_>>()
     );
-    // Generate the party_shares: for each key id we own, we take our received key share at that
-    // index
-    let party_shares = signer
-        .key_ids
+
+    // Check every received share against its dealer's polynomial commitment and complain
+    // about any dealer whose shares do not verify
+    let accused = state
+        .shares
         .iter()
-        .copied()
-        .map(|key_id| {
-            let mut key_shares = HashMap::new();
+        .filter(|(dealer, shares)| {
+            !state
+                .poly_commitments
+                .get(dealer)
+                .is_some_and(|commitment| verify_shares(shares, &signer.key_ids, commitment))
+        })
+        .map(|(dealer, _)| *dealer)
+        .sorted()
+        .collect_vec();
 
-            for (id, shares) in &state.shares {
-                key_shares.insert(*id, shares[&key_id]);
-            }
+    if !accused.is_empty() {
+        warn!(
+            "Party {} complaining about invalid shares from {accused:?}",
+            signer.party_id
+        );
+    }
 
-            (key_id, key_shares.into_iter().collect())
-        })
-        .collect();
+    let my_complaint = KeygenComplaintMsg {
+        source: signer.party_id,
+        accused,
+    };
+    send_message::<M, _>(Msg::KeygenComplaint(my_complaint.clone()), &mut outgoings).await?;
 
-    let polys = state
-        .poly_commitments
-        .iter()
-        .sorted_by(|a, b| a.0.cmp(b.0))
-        .map(|r| r.1.clone())
+    // Complaints are keyed by their real sender. Otherwise a party could complain in another
+    // party's name and make every dealer reveal that party's shares
+    let complaints = rounds
+        .complete(round3)
+        .await
+        .map_err(|err| KeygenError::MpcError(err.to_string()))?;
+    let complaints = collect_by_sender(complaints, signer.party_id, my_complaint)?
+        .into_values()
         .collect_vec();
 
-    signer
-        .compute_secret(&party_shares, &polys)
+    // Answer every complaint against us by revealing the shares we sent to the complainer
+    let my_justification = KeygenJustificationMsg {
+        source: signer.party_id,
+        shares: complaints
+            .iter()
+            .filter(|complaint| complaint.accused.contains(&signer.party_id))
+            .map(|complaint| {
+                let revealed = party_key_ids
+                    .get(complaint.source as usize)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|key_id| shares.get(key_id).map(|share| (*key_id, *share)))
+                    .collect();
+                (complaint.source, revealed)
+            })
+            .collect(),
+    };
+    send_message::<M, _>(
+        Msg::KeygenJustification(my_justification.clone()),
+        &mut outgoings,
+    )
+    .await?;
+
+    let justifications = rounds
+        .complete(round4)
+        .await
         .map_err(|err| KeygenError::MpcError(err.to_string()))?;
+    let justifications = collect_by_sender(justifications, signer.party_id, my_justification)?;
+
+    // Every party sees the same complaints and justifications, so every honest party reaches
+    // the same verdict on which dealers are disqualified
+    let mut disqualified = BTreeSet::new();
+    for complaint in &complaints {
+        for dealer in &complaint.accused {
+            let revealed = justifications
+                .get(dealer)
+                .and_then(|justification| justification.shares.get(&complaint.source));
+            let complainer_key_ids = party_key_ids
+                .get(complaint.source as usize)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            match (revealed, state.poly_commitments.get(dealer)) {
+                (Some(revealed), Some(commitment))
+                    if verify_shares(revealed, complainer_key_ids, commitment) =>
+                {
+                    // The dealer proved the shares it sent were valid, so the complainer
+                    // uses the revealed ones
+                    if complaint.source == signer.party_id {
+                        state.shares.insert(*dealer, revealed.clone());
+                    }
+                }
+                _ => {
+                    disqualified.insert(*dealer);
+                }
+            }
+        }
+    }
 
-    let party = signer.save();
+    if !disqualified.is_empty() {
+        warn!("Disqualified keygen dealers: {disqualified:?}");
+        state
+            .shares
+            .retain(|dealer, _| !disqualified.contains(dealer));
+        state
+            .poly_commitments
+            .retain(|dealer, _| !disqualified.contains(dealer));
+    }
+
+    if state.poly_commitments.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: disqualified.into_iter().collect(),
+            reason: BlameReason::InvalidShares,
+        });
+    }
+
+    // Our key shares are the sums of the shares the qualified dealers sent us, and the group
+    // key the sum of their constant terms. Every share left was checked against its dealer's
+    // commitment above, so there is nothing left for `compute_secret` to verify, and it would
+    // refuse to run without a share from every party in the session
+    let mut party = signer.save();
+    party.private_keys = signer
+        .key_ids
+        .iter()
+        .map(|key_id| {
+            let mut private_key = Scalar::from(0);
+            for (dealer, shares) in &state.shares {
+                let share = shares.get(key_id).ok_or_else(|| {
+                    KeygenError::MpcError(format!(
+                        "Party {dealer} sent no share for key id {key_id}"
+                    ))
+                })?;
+                private_key += *share;
+            }
+
+            Ok((*key_id, private_key))
+        })
+        .collect::<Result<_, KeygenError>>()?;
+    party.group_key = state
+        .poly_commitments
+        .values()
+        .filter_map(|commitment| commitment.A.first())
+        .fold(Point::new(), |group_key, public| group_key + *public);
 
     // Convert the WSTS group key into a FROST-compatible format
     let group_point = party.group_key;
@@ -150,6 +575,126 @@ where
     Ok(state)
 }
 
+/// A keygen message that names the party it comes from
+trait SourcedMsg {
+    fn source(&self) -> u32;
+}
+
+macro_rules! impl_sourced_msg {
+    ($($msg:ty),*) => {
+        $(impl SourcedMsg for $msg {
+            fn source(&self) -> u32 {
+                self.source
+            }
+        })*
+    };
+}
+
+impl_sourced_msg!(
+    KeygenMsg,
+    KeygenSharesMsg,
+    KeygenEchoMsg,
+    KeygenComplaintMsg,
+    KeygenJustificationMsg
+);
+
+/// Collects the messages of a round, including our own, by the party that actually sent them.
+/// The `source` of a message is filled in by its sender, so any sender that claims to be
+/// another party is blamed instead of having its message filed under that party
+fn collect_by_sender<T: SourcedMsg>(
+    msgs: RoundMsgs<T>,
+    party_id: u32,
+    my_msg: T,
+) -> Result<BTreeMap<u32, T>, KeygenError> {
+    let mut by_sender = BTreeMap::from([(party_id, my_msg)]);
+    let mut impostors = Vec::new();
+    for (sender, _, msg) in msgs.into_iter_indexed() {
+        let sender = sender as u32;
+        if msg.source() == sender {
+            by_sender.insert(sender, msg);
+        } else {
+            impostors.push(sender);
+        }
+    }
+
+    if !impostors.is_empty() {
+        return Err(KeygenError::Blame {
+            culprits: impostors.into_iter().sorted().collect(),
+            reason: BlameReason::Impersonation,
+        });
+    }
+
+    Ok(by_sender)
+}
+
+/// The digest a dealer signs for its [`KeygenMsg`], bound to the keygen session
+pub fn broadcast_signing_digest(ctx: &[u8; 32], commitment_digest: &[u8; 32]) -> [u8; 32] {
+    crate::compute_sha256_hash!(BROADCAST_SIGNATURE_SALT, ctx, commitment_digest)
+}
+
+/// Checks that `dealer` signed `commitment_digest` in this session with its identity key
+fn verify_broadcast_signature(
+    ctx: &[u8; 32],
+    parties: &BTreeMap<u16, K256VerifyingKey>,
+    dealer: u32,
+    commitment_digest: &[u8; 32],
+    signature: &K256Signature,
+) -> bool {
+    let digest = broadcast_signing_digest(ctx, commitment_digest);
+    u16::try_from(dealer)
+        .ok()
+        .and_then(|dealer| parties.get(&dealer))
+        .is_some_and(|public_key| K256Ecdsa::verify(public_key, &digest, signature))
+}
+
+/// Hashes everything a dealer broadcast in its [`KeygenMsg`] that ends up in the group key
+pub fn commitment_digest(msg: &KeygenMsg) -> [u8; 32] {
+    digest_commitment(msg.source, &msg.key_ids, &msg.poly_commitment)
+}
+
+/// [`commitment_digest`] of a [`KeygenMsg`] from its parts, for signing it before it is built
+fn digest_commitment(source: u32, key_ids: &[u32], poly_commitment: &PolyCommitment) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.update(source.to_be_bytes());
+    for key_id in key_ids {
+        hasher.update(key_id.to_be_bytes());
+    }
+    for point in &poly_commitment.A {
+        hasher.update(point.compress().data);
+    }
+    hasher.update(poly_commitment.id.id.to_bytes());
+    hasher.update(poly_commitment.id.kG.compress().data);
+    hasher.update(poly_commitment.id.kca.to_bytes());
+
+    hasher.finalize().into()
+}
+
+/// Checks a private share for `key_id` against the dealer's public polynomial commitment
+fn verify_share(share: &Scalar, key_id: u32, commitment: &PolyCommitment) -> bool {
+    let x = wsts::compute::id(key_id);
+    // Evaluate the committed polynomial at `x` in the exponent using Horner's method
+    let expected = commitment
+        .A
+        .iter()
+        .rev()
+        .fold(Point::new(), |acc, coefficient| acc * x + *coefficient);
+
+    *share * G == expected
+}
+
+/// Checks that `shares` holds a valid share for each of `key_ids`
+pub(crate) fn verify_shares(
+    shares: &HashMap<u32, Scalar>,
+    key_ids: &[u32],
+    commitment: &PolyCommitment,
+) -> bool {
+    key_ids.iter().all(|key_id| {
+        shares
+            .get(key_id)
+            .is_some_and(|share| verify_share(share, *key_id, commitment))
+    })
+}
+
 pub trait HasRecipient {
     fn recipient(&self) -> MessageDestination;
 }
@@ -157,7 +702,11 @@ pub trait HasRecipient {
 impl HasRecipient for Msg {
     fn recipient(&self) -> MessageDestination {
         match self {
-            Msg::KeygenBroadcast(_) => MessageDestination::AllParties,
+            Msg::KeygenBroadcast(_)
+            | Msg::KeygenEcho(_)
+            | Msg::KeygenComplaint(_)
+            | Msg::KeygenJustification(_) => MessageDestination::AllParties,
+            Msg::KeygenShares(msg) => MessageDestination::OneParty(msg.destination as _),
         }
     }
 }
@@ -1,8 +1,16 @@
+pub mod backup;
 pub mod context;
 pub mod keygen;
-pub(crate) mod keygen_state_machine;
+pub mod keygen_state_machine;
+pub mod policy;
+pub mod preprocessing;
+pub mod preprocessing_state_machine;
+pub mod psbt;
+pub mod schema;
 pub mod signing;
-pub(crate) mod signing_state_machine;
+pub mod signing_state_machine;
+pub mod store;
+pub mod transcript;
 pub mod utils;
 
 pub use blueprint_sdk::*;
@@ -44,3 +52,16 @@ pub fn compute_execution_hashes(
 
     (interexecution_hash, intraexecution_hash)
 }
+
+/// Helper function to compute the execution id of a single session against an existing key,
+/// such as signing or nonce preprocessing. The hashes from [`compute_execution_hashes`] are
+/// shared by every job against the same key, so this also commits to the job's own call_id
+/// and to its payload, e.g. the message being signed
+pub fn compute_session_hash(
+    key_execution_hash: [u8; 32],
+    call_id: u64,
+    payload: &[u8],
+) -> [u8; 32] {
+    let message_digest = compute_sha256_hash!(payload);
+    compute_sha256_hash!(key_execution_hash, call_id.to_be_bytes(), message_digest)
+}
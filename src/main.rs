@@ -3,11 +3,19 @@ use blueprint_sdk::macros;
 use blueprint_sdk::runners::core::runner::BlueprintRunner;
 use blueprint_sdk::runners::tangle::tangle::TangleConfig;
 use color_eyre::Result;
-use wsts_blueprint::context::WstsContext;
+use wsts_blueprint::backup::BackupCommand;
+use wsts_blueprint::context::{StoreContext, WstsContext};
 use wsts_blueprint::crypto::KeyEncoding;
 
 #[macros::main(env)]
 async fn main() {
+    // Admin actions on the key store run instead of the blueprint, before the operator joins
+    // the network
+    if let Some(command) = BackupCommand::from_env()? {
+        command.run(&StoreContext::new(env.clone())?).await?;
+        return Ok(());
+    }
+
     let context = WstsContext::new(env.clone())?;
 
     info!(
@@ -20,10 +28,23 @@ async fn main() {
     let tangle_config = TangleConfig::default();
     let keygen = wsts_blueprint::keygen::KeygenEventHandler::new(&env, context.clone()).await?;
     let signing = wsts_blueprint::signing::SignEventHandler::new(&env, context.clone()).await?;
+    let detailed_signing =
+        wsts_blueprint::signing::SignWithDetailsEventHandler::new(&env, context.clone()).await?;
+    let batch_signing =
+        wsts_blueprint::signing::SignBatchEventHandler::new(&env, context.clone()).await?;
+    let psbt_signing =
+        wsts_blueprint::psbt::SignPsbtEventHandler::new(&env, context.clone()).await?;
+    let preprocessing =
+        wsts_blueprint::preprocessing::PreprocessNoncesEventHandler::new(&env, context.clone())
+            .await?;
 
     BlueprintRunner::new(tangle_config, env.clone())
         .job(keygen)
         .job(signing)
+        .job(detailed_signing)
+        .job(batch_signing)
+        .job(psbt_signing)
+        .job(preprocessing)
         .run()
         .await?;
 
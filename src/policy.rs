@@ -0,0 +1,399 @@
+use crate::context::WstsContext;
+use crate::signing::SigningError;
+use crate::utils::MessageMode;
+use blueprint_sdk::crypto::k256::{K256Ecdsa, K256Signature, K256VerifyingKey};
+use blueprint_sdk::crypto::KeyType;
+use blueprint_sdk::event_listeners::core::Error as EventListenerError;
+use blueprint_sdk::event_listeners::tangle::error::TangleEventListenerError;
+use blueprint_sdk::event_listeners::tangle::events::TangleEvent;
+use blueprint_sdk::event_listeners::tangle::services::{services_pre_processor, TangleJobEvent};
+use blueprint_sdk::networking::GossipMsgKeyPair;
+use blueprint_sdk::tangle_subxt::subxt::utils::AccountId32;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::services::events::JobCalled;
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The name of the file in the keystore directory that holds the operator's signing policy
+pub const SIGNING_POLICY_FILE: &str = "signing-policy.json";
+
+/// Domain separator for the digest signed by a [`SigningRefusal`]
+const REFUSAL_SALT: &str = "wsts-signing-refusal";
+
+/// The rules an operator applies to every signing request before taking part in it. Every rule
+/// is optional, and an empty policy accepts every request. Message rules apply to the messages
+/// as given to the job, before they are hashed for the requested message mode
+///
+/// PSBT signing requests sign BIP341 sighashes that every operator computes from the
+/// transaction itself, so they have no caller-chosen prefix and are exempt from
+/// `allowed_prefixes`. Since a sighash is the BIP340 tagged hash `TapSighash` of the
+/// transaction, they are only accepted under `allowed_domain_tags` if `TapSighash` is one of
+/// the allowed tags. The other rules apply to them as usual
+///
+/// The policy is read from [`SIGNING_POLICY_FILE`] in the keystore directory, e.g.
+///
+/// ```json
+/// {
+///     "max_message_len": 32,
+///     "allowed_prefixes": ["54617052", "deadbeef"],
+///     "allowed_domain_tags": ["5461704c65616648617368"],
+///     "rate_limit": { "max_messages": 100, "window_secs": 3600 },
+///     "allowed_callers": ["d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"]
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningPolicy {
+    /// The longest message that may be signed, in bytes
+    pub max_message_len: Option<usize>,
+    /// Hex-encoded prefixes, such as domain tags. If any are given, every message must start
+    /// with one of them
+    pub allowed_prefixes: Vec<String>,
+    /// Hex-encoded BIP340 domain tags. If any are given, every message must be signed in the
+    /// tagged message mode with one of them
+    pub allowed_domain_tags: Vec<String>,
+    /// Limits how many messages may be signed with each key. Only messages that were actually
+    /// signed count towards the limit
+    pub rate_limit: Option<RateLimit>,
+    /// Hex-encoded account ids allowed to request signatures. If empty, any caller may
+    pub allowed_callers: Vec<String>,
+}
+
+/// Allows at most `max_messages` messages to be signed with a key in any `window_secs` seconds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub max_messages: usize,
+    pub window_secs: u64,
+}
+
+/// Where the messages of a signing session come from, which decides the rules of the
+/// [`SigningPolicy`] they are checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageSource {
+    /// The messages were given by the caller of the job
+    Caller,
+    /// The messages are BIP341 sighashes the operators computed from a PSBT
+    PsbtSighashes,
+}
+
+/// The BIP340 tag of BIP341 sighashes, which PSBT signing requests are checked against
+const TAP_SIGHASH_TAG: &[u8] = b"TapSighash";
+
+/// Why a [`PolicyEngine`] rejected a signing request
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyViolation {
+    #[error("message of {len} bytes is longer than the allowed {max} bytes")]
+    MessageTooLong { len: usize, max: usize },
+
+    #[error("message does not start with an allowed prefix")]
+    PrefixNotAllowed,
+
+    #[error("message is not tagged with an allowed domain tag")]
+    DomainTagNotAllowed,
+
+    #[error("more than {max_messages} messages signed with this key in {window_secs} seconds")]
+    RateLimited {
+        max_messages: usize,
+        window_secs: u64,
+    },
+
+    #[error("caller {0} is not allowed to request signatures")]
+    CallerNotAllowed(String),
+
+    #[error("the caller of the job is unknown")]
+    UnknownCaller,
+}
+
+/// Applies an operator's [`SigningPolicy`], keeping track of how many messages each key has
+/// signed recently
+pub struct PolicyEngine {
+    max_message_len: Option<usize>,
+    allowed_prefixes: Vec<Vec<u8>>,
+    allowed_domain_tags: HashSet<Vec<u8>>,
+    rate_limit: Option<RateLimit>,
+    allowed_callers: HashSet<[u8; 32]>,
+    /// When each recently signed message, or message still being signed, was accepted, by
+    /// store key
+    usage: parking_lot::Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl PolicyEngine {
+    pub fn new(policy: SigningPolicy) -> eyre::Result<Self> {
+        let allowed_prefixes = policy
+            .allowed_prefixes
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<_, _>>()
+            .map_err(|err| eyre::eyre!("Invalid allowed prefix: {err}"))?;
+
+        let allowed_domain_tags = policy
+            .allowed_domain_tags
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<_, _>>()
+            .map_err(|err| eyre::eyre!("Invalid allowed domain tag: {err}"))?;
+
+        let allowed_callers = policy
+            .allowed_callers
+            .iter()
+            .map(|caller| {
+                let mut account = [0u8; 32];
+                hex::decode_to_slice(caller, &mut account)
+                    .map_err(|err| eyre::eyre!("Invalid allowed caller {caller}: {err}"))?;
+                Ok(account)
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(Self {
+            max_message_len: policy.max_message_len,
+            allowed_prefixes,
+            allowed_domain_tags,
+            rate_limit: policy.rate_limit,
+            allowed_callers,
+            usage: parking_lot::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Loads the policy from [`SIGNING_POLICY_FILE`] in `keystore_dir`, accepting every request
+    /// if there is no such file
+    pub fn load(keystore_dir: &Path) -> eyre::Result<Self> {
+        let path = keystore_dir.join(SIGNING_POLICY_FILE);
+        let policy = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| eyre::eyre!("Invalid signing policy {}: {err}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => SigningPolicy::default(),
+            Err(err) => {
+                return Err(eyre::eyre!(
+                    "Failed to read signing policy {}: {err}",
+                    path.display()
+                ))
+            }
+        };
+
+        Self::new(policy)
+    }
+
+    /// Checks a request by `caller` to sign `messages`, as given to the job, in the message
+    /// `mode` with the key stored under `store_key`
+    ///
+    /// The accepted messages are held against the key's rate limit by the returned permit, so
+    /// that concurrent requests cannot go over it together. They only count towards the limit
+    /// once the permit is [charged](RateLimitPermit::charge), after they were signed, and are
+    /// released if it is dropped instead
+    pub fn check(
+        &self,
+        store_key: &str,
+        caller: Option<&AccountId32>,
+        mode: &MessageMode,
+        messages: &[Vec<u8>],
+    ) -> Result<RateLimitPermit<'_>, PolicyViolation> {
+        self.check_caller(caller)?;
+
+        if !self.allowed_domain_tags.is_empty()
+            && !matches!(mode, MessageMode::Tagged(tag) if self.allowed_domain_tags.contains(tag))
+        {
+            return Err(PolicyViolation::DomainTagNotAllowed);
+        }
+
+        for message in messages {
+            self.check_len(message)?;
+
+            if !self.allowed_prefixes.is_empty()
+                && !self
+                    .allowed_prefixes
+                    .iter()
+                    .any(|prefix| message.starts_with(prefix))
+            {
+                return Err(PolicyViolation::PrefixNotAllowed);
+            }
+        }
+
+        self.reserve(store_key, messages.len())
+    }
+
+    /// Like [`check`](Self::check), for a request by `caller` to sign the BIP341 `sighashes`
+    /// of a PSBT. See [`SigningPolicy`] for the rules that apply to them
+    pub fn check_psbt(
+        &self,
+        store_key: &str,
+        caller: Option<&AccountId32>,
+        sighashes: &[Vec<u8>],
+    ) -> Result<RateLimitPermit<'_>, PolicyViolation> {
+        self.check_caller(caller)?;
+
+        if !self.allowed_domain_tags.is_empty()
+            && !self.allowed_domain_tags.contains(TAP_SIGHASH_TAG)
+        {
+            return Err(PolicyViolation::DomainTagNotAllowed);
+        }
+
+        for sighash in sighashes {
+            self.check_len(sighash)?;
+        }
+
+        self.reserve(store_key, sighashes.len())
+    }
+
+    fn check_caller(&self, caller: Option<&AccountId32>) -> Result<(), PolicyViolation> {
+        if !self.allowed_callers.is_empty() {
+            let caller = caller.ok_or(PolicyViolation::UnknownCaller)?;
+            if !self.allowed_callers.contains(&caller.0) {
+                return Err(PolicyViolation::CallerNotAllowed(hex::encode(caller.0)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_len(&self, message: &[u8]) -> Result<(), PolicyViolation> {
+        match self.max_message_len {
+            Some(max) if message.len() > max => Err(PolicyViolation::MessageTooLong {
+                len: message.len(),
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Holds `count` messages against the rate limit of the key stored under `store_key`
+    fn reserve(
+        &self,
+        store_key: &str,
+        count: usize,
+    ) -> Result<RateLimitPermit<'_>, PolicyViolation> {
+        let now = Instant::now();
+        let Some(rate_limit) = self.rate_limit else {
+            return Ok(RateLimitPermit::new(self, store_key, now, 0));
+        };
+
+        let window = Duration::from_secs(rate_limit.window_secs);
+        let mut usage = self.usage.lock();
+        let signed = usage.entry(store_key.to_string()).or_default();
+
+        while signed
+            .front()
+            .is_some_and(|accepted| now.duration_since(*accepted) >= window)
+        {
+            signed.pop_front();
+        }
+
+        if signed.len() + count > rate_limit.max_messages {
+            return Err(PolicyViolation::RateLimited {
+                max_messages: rate_limit.max_messages,
+                window_secs: rate_limit.window_secs,
+            });
+        }
+
+        signed.extend(std::iter::repeat(now).take(count));
+        Ok(RateLimitPermit::new(self, store_key, now, count))
+    }
+}
+
+/// Messages accepted by a [`PolicyEngine`] that are held against a key's rate limit while they
+/// are being signed. They are released when the permit is dropped, unless it was
+/// [charged](Self::charge)
+#[must_use = "the messages are released unless the permit is charged"]
+pub struct RateLimitPermit<'a> {
+    engine: &'a PolicyEngine,
+    store_key: String,
+    accepted: Instant,
+    count: usize,
+}
+
+impl<'a> RateLimitPermit<'a> {
+    fn new(engine: &'a PolicyEngine, store_key: &str, accepted: Instant, count: usize) -> Self {
+        Self {
+            engine,
+            store_key: store_key.to_string(),
+            accepted,
+            count,
+        }
+    }
+
+    /// Counts the messages towards the key's rate limit, once they were signed
+    pub fn charge(mut self) {
+        self.count = 0;
+    }
+}
+
+impl Drop for RateLimitPermit<'_> {
+    fn drop(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+
+        let mut usage = self.engine.usage.lock();
+        let Some(signed) = usage.get_mut(&self.store_key) else {
+            return;
+        };
+
+        // Every message accepted at the same instant is interchangeable, so release any of them
+        let mut released = 0;
+        signed.retain(|accepted| {
+            if released < self.count && *accepted == self.accepted {
+                released += 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// A party's signed statement that its policy rejected a signing session. It is sent to the
+/// other parties in place of its protocol messages, so that they can abort right away instead
+/// of waiting for the session to time out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningRefusal {
+    pub party_id: u32,
+    pub session_id: [u8; 32],
+    pub reason: String,
+    /// The party's signature over the refusal with its operator identity key
+    pub signature: K256Signature,
+}
+
+impl SigningRefusal {
+    pub fn new(
+        identity: &GossipMsgKeyPair,
+        party_id: u32,
+        session_id: [u8; 32],
+        reason: String,
+    ) -> Result<Self, SigningError> {
+        let digest = Self::digest(party_id, session_id, &reason);
+        let signature = K256Ecdsa::sign_with_secret(&mut identity.clone(), &digest)
+            .map_err(|err| SigningError::ContextError(err.to_string()))?;
+
+        Ok(Self {
+            party_id,
+            session_id,
+            reason,
+            signature,
+        })
+    }
+
+    /// Checks that the refusal was signed by the operator with the given identity key
+    pub fn verify(&self, public_key: &K256VerifyingKey) -> bool {
+        let digest = Self::digest(self.party_id, self.session_id, &self.reason);
+        K256Ecdsa::verify(public_key, &digest, &self.signature)
+    }
+
+    fn digest(party_id: u32, session_id: [u8; 32], reason: &str) -> [u8; 32] {
+        crate::compute_sha256_hash!(REFUSAL_SALT, session_id, party_id.to_be_bytes(), reason)
+    }
+}
+
+/// Runs [`services_pre_processor`], then records the account that called the job in the
+/// context so that the signing policy can check it
+pub async fn caller_pre_processor(
+    event: TangleEvent<WstsContext, JobCalled>,
+) -> Result<Option<TangleJobEvent<WstsContext>>, EventListenerError<TangleEventListenerError>> {
+    let caller = event.evt.caller.clone();
+    let job_event = services_pre_processor(event).await?;
+
+    Ok(job_event.map(|mut job_event| {
+        job_event.context.caller = Some(caller);
+        job_event
+    }))
+}
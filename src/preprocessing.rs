@@ -0,0 +1,136 @@
+use crate::context::WstsContext;
+use crate::signing::SigningError;
+use blueprint_sdk::crypto::k256::K256VerifyingKey;
+use blueprint_sdk::crypto::KeyEncoding;
+use blueprint_sdk::event_listeners::tangle::events::TangleEventListener;
+use blueprint_sdk::event_listeners::tangle::services::{
+    services_post_processor, services_pre_processor,
+};
+use blueprint_sdk::logging::info;
+use blueprint_sdk::macros::ext::contexts::tangle::TangleClientContext;
+use blueprint_sdk::networking::round_based_compat::NetworkDeliveryWrapper;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::services::events::JobCalled;
+use blueprint_sdk::{job, macros as gadget_macros};
+use gadget_macros::ext::clients::GadgetServicesClient;
+use std::collections::BTreeMap;
+
+/// Configuration constants for the WSTS nonce preprocessing process
+const PREPROCESSING_SALT: &str = "wsts-preprocessing";
+
+#[job(
+    id = 2,
+    params(keygen_call_id, count),
+    event_listener(
+        listener = TangleEventListener<WstsContext, JobCalled>,
+        pre_processor = services_pre_processor,
+        post_processor = services_post_processor,
+    ),
+)]
+/// Generates and exchanges a batch of signing nonces for a previously generated key
+///
+/// Later signing jobs against the key take their nonces from the batch, as named by the
+/// session's coordinator, and skip waiting for the nonce round. Every operator from keygen must
+/// take part in preprocessing, and every operator signs when a preprocessed nonce is used, so
+/// such a session fails if any of them is offline
+///
+/// # Arguments
+/// * `keygen_call_id` - The call id of the keygen job that generated the key
+/// * `count` - The number of nonces to generate
+/// * `context` - The WSTS context containing network and storage configuration
+///
+/// # Returns
+/// Returns the number of preprocessed nonces available for the key
+///
+/// # Errors
+/// Returns an error if:
+/// - Failed to retrieve blueprint ID or call ID
+/// - Failed to retrieve the key entry
+/// - Preprocessing protocol failed
+pub async fn preprocess_nonces(
+    keygen_call_id: u64,
+    count: u16,
+    context: WstsContext,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    // Get configuration and compute deterministic values
+    let client = context.tangle_client().await?;
+    let blueprint_id = client
+        .blueprint_id()
+        .await
+        .map_err(|e| SigningError::ContextError(e.to_string()))?;
+
+    let call_id = context
+        .call_id
+        .ok_or_else(|| SigningError::ContextError("call_id not set".into()))?;
+
+    // Setup party information
+    let (i, operators) = client
+        .get_party_index_and_operators()
+        .await
+        .map_err(|e| SigningError::ContextError(e.to_string()))?;
+
+    let parties: BTreeMap<u16, _> = operators
+        .into_iter()
+        .enumerate()
+        .map(|(j, (_, ecdsa))| {
+            (
+                j as u16,
+                K256VerifyingKey::from_bytes(&ecdsa.0).expect("33 byte compressed ECDSA key"),
+            )
+        })
+        .collect();
+
+    let n = parties.len() as u16;
+    let i = i as u16;
+
+    // Compute hash for key retrieval. Must use the call_id of the keygen job
+    let (_, key_execution_hash) =
+        crate::compute_execution_hashes(n, blueprint_id, keygen_call_id, PREPROCESSING_SALT);
+    let deterministic_hash =
+        crate::compute_session_hash(key_execution_hash, call_id, &count.to_be_bytes());
+
+    // Retrieve the key entry
+    let store_key = context
+        .key_id(keygen_call_id)
+        .map_err(|e| SigningError::ContextError(e.to_string()))?
+        .store_key();
+    let state = context
+        .store
+        .get(&store_key)
+        .map_err(|e| SigningError::ContextError(e.to_string()))?
+        .ok_or_else(|| SigningError::ContextError("Key entry not found".to_string()))?;
+
+    info!(
+        "Starting WSTS nonce preprocessing for party {i}, n={n}, eid={}",
+        hex::encode(deterministic_hash)
+    );
+
+    let network = NetworkDeliveryWrapper::new(
+        context.network_backend.clone(),
+        i,
+        deterministic_hash,
+        parties.clone(),
+    );
+
+    let mut rng = rand::rngs::OsRng;
+
+    let network = round_based::party::MpcParty::connected(network);
+
+    let batch = crate::preprocessing_state_machine::wsts_preprocessing_protocol(
+        network,
+        &state,
+        call_id,
+        count as usize,
+        &mut rng,
+    )
+    .await?;
+
+    let remaining = context
+        .update_key_state(&store_key, |state| {
+            state.nonce_pool.add(batch.clone());
+            state.nonce_pool.remaining()
+        })
+        .map_err(|e| SigningError::ContextError(e.to_string()))?
+        .ok_or_else(|| SigningError::ContextError("Key entry not found".to_string()))?;
+
+    Ok(remaining as u64)
+}
@@ -0,0 +1,384 @@
+use rand::{CryptoRng, RngCore};
+use round_based::{
+    rounds_router::{simple_store::RoundInput, RoundsRouter},
+    Delivery, MessageDestination, Mpc, MpcParty, ProtocolMessage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::keygen_state_machine::{HasRecipient, WstsState};
+use crate::signing::SigningError;
+use blueprint_sdk::logging::info;
+use itertools::Itertools;
+use round_based::SinkExt;
+use wsts::common::{Nonce, PublicNonce};
+use wsts::v2::Party;
+
+/// Nonces exchanged ahead of time by preprocessing jobs, so that signing only needs to run
+/// the signature share round
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct NoncePool {
+    pub batches: Vec<NonceBatch>,
+}
+
+/// The nonces produced by a single preprocessing job
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct NonceBatch {
+    /// The call id of the preprocessing job that produced this batch
+    pub batch_id: u64,
+    /// Every party's public nonces, indexed by slot
+    pub public_nonces: BTreeMap<u32, Vec<PublicNonce>>,
+    /// Our secret nonces, indexed by slot. A slot is emptied when its nonce is used
+    pub secret_nonces: Vec<Option<Nonce>>,
+}
+
+/// Where a nonce is in its lifecycle. A nonce only ever moves forward through these states,
+/// and is never used to sign once it is [`NonceStatus::Consumed`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NonceStatus {
+    /// The nonce was generated but has not been sent to the other parties
+    Generated,
+    /// The public nonce was sent to the other parties
+    Committed,
+    /// The nonce was used to produce a signature share
+    Consumed,
+}
+
+/// How long a session's nonce ledger is honoured after it last moved. Secret nonces are never
+/// persisted, so a ledger only outlives its session when the process died mid-session, and
+/// the nonces it records died with it. Once it expires the session may be rerun with fresh
+/// nonces instead of being refused forever
+pub const NONCE_LEDGER_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The persisted lifecycle of the nonce used for one message of a signing session. A session's
+/// records are stored together, one per message, under its
+/// [`nonce_ledger_key`](crate::store::nonce_ledger_key)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NonceRecord {
+    pub status: NonceStatus,
+    pub public_nonce: PublicNonce,
+    /// When the record last moved, in seconds since the Unix epoch. Records from before this
+    /// was kept read as 0, and so as expired
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl NonceRecord {
+    pub fn new(public_nonce: PublicNonce, status: NonceStatus, now: u64) -> Self {
+        NonceRecord {
+            status,
+            public_nonce,
+            updated_at: now,
+        }
+    }
+
+    /// Moves the record forward to `status`, refusing any transition that would let the
+    /// nonce be used twice: going backwards, staying put, or switching to another nonce
+    pub fn advance(
+        &mut self,
+        public_nonce: &PublicNonce,
+        status: NonceStatus,
+        now: u64,
+    ) -> Result<(), SigningError> {
+        if !same_nonce(&self.public_nonce, public_nonce) {
+            return Err(SigningError::NonceReuse(
+                "session already used a different nonce".to_string(),
+            ));
+        }
+
+        if status <= self.status {
+            return Err(SigningError::NonceReuse(format!(
+                "nonce is already {:?}, refusing to move it to {status:?}",
+                self.status
+            )));
+        }
+
+        self.status = status;
+        self.updated_at = now;
+        Ok(())
+    }
+
+    /// Whether the record is older than [`NONCE_LEDGER_TTL`] at `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.updated_at) >= NONCE_LEDGER_TTL.as_secs()
+    }
+}
+
+/// Moves a session's records, one per message, to `status` for `public_nonces`, starting the
+/// ledger if it is empty or every record in it has expired
+pub fn advance_ledger(
+    records: &mut Vec<NonceRecord>,
+    public_nonces: &[PublicNonce],
+    status: NonceStatus,
+    now: u64,
+) -> Result<(), SigningError> {
+    if records.iter().all(|record| record.is_expired(now)) {
+        *records = public_nonces
+            .iter()
+            .map(|public_nonce| NonceRecord::new(public_nonce.clone(), status, now))
+            .collect();
+        return Ok(());
+    }
+
+    if records.len() != public_nonces.len() {
+        return Err(SigningError::NonceReuse(
+            "session already used a different number of nonces".to_string(),
+        ));
+    }
+
+    records
+        .iter_mut()
+        .zip(public_nonces)
+        .try_for_each(|(record, public_nonce)| record.advance(public_nonce, status, now))
+}
+
+pub(crate) fn same_nonce(a: &PublicNonce, b: &PublicNonce) -> bool {
+    a.D == b.D && a.E == b.E
+}
+
+/// Identifies a preprocessed nonce by the batch it came from and its slot within the batch.
+/// Slots are ordered by batch first, which is the order the pool hands them out in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NonceSlot {
+    pub batch_id: u64,
+    pub index: u32,
+}
+
+/// A preprocessed nonce taken out of the [`NoncePool`] for a signing session
+#[derive(Clone)]
+pub struct PreprocessedNonce {
+    pub slot: NonceSlot,
+    pub secret: Nonce,
+    pub public_nonces: BTreeMap<u32, PublicNonce>,
+}
+
+impl NoncePool {
+    /// Adds a batch to the pool, keeping the batches in the order of their ids so that every
+    /// party hands out its nonces in the same order
+    pub fn add(&mut self, batch: NonceBatch) {
+        let position = self
+            .batches
+            .partition_point(|existing| existing.batch_id < batch.batch_id);
+        self.batches.insert(position, batch);
+    }
+
+    /// Takes the first unused nonce out of the pool. The caller must persist the pool before
+    /// using the nonce, otherwise the same nonce could be taken again
+    pub fn take(&mut self) -> Option<PreprocessedNonce> {
+        for batch in &mut self.batches {
+            let Some(index) = batch.secret_nonces.iter().position(Option::is_some) else {
+                continue;
+            };
+
+            return batch.take(index);
+        }
+
+        None
+    }
+
+    /// Takes exactly the nonces in `slots` out of the pool, in order, or none at all if any of
+    /// them is missing or was already used. Every unused nonce before the first slot is
+    /// dropped too, since the session that named the slots skipped past it, which keeps the
+    /// pool in step with the other parties' after sessions we missed. The caller must persist
+    /// the pool before using the nonces
+    pub fn take_slots(&mut self, slots: &[NonceSlot]) -> Option<Vec<PreprocessedNonce>> {
+        let first = *slots.iter().min()?;
+        let available = slots.iter().all_unique()
+            && slots.iter().all(|slot| {
+                self.batch(slot.batch_id)
+                    .and_then(|batch| batch.secret_nonces.get(slot.index as usize))
+                    .is_some_and(Option::is_some)
+            });
+        if !available {
+            return None;
+        }
+
+        for batch in &mut self.batches {
+            for (index, secret) in batch.secret_nonces.iter_mut().enumerate() {
+                let slot = NonceSlot {
+                    batch_id: batch.batch_id,
+                    index: index as u32,
+                };
+                if slot < first {
+                    *secret = None;
+                }
+            }
+        }
+
+        slots
+            .iter()
+            .map(|slot| {
+                self.batches
+                    .iter_mut()
+                    .find(|batch| batch.batch_id == slot.batch_id)?
+                    .take(slot.index as usize)
+            })
+            .collect()
+    }
+
+    fn batch(&self, batch_id: u64) -> Option<&NonceBatch> {
+        self.batches.iter().find(|batch| batch.batch_id == batch_id)
+    }
+
+    /// The number of nonces left in the pool
+    pub fn remaining(&self) -> usize {
+        self.batches
+            .iter()
+            .map(|batch| batch.secret_nonces.iter().flatten().count())
+            .sum()
+    }
+
+    /// Drops batches whose nonces have all been used
+    pub fn prune(&mut self) {
+        self.batches
+            .retain(|batch| batch.secret_nonces.iter().any(Option::is_some));
+    }
+}
+
+impl NonceBatch {
+    /// Takes the nonce in slot `index` out of the batch, if it is still unused
+    fn take(&mut self, index: usize) -> Option<PreprocessedNonce> {
+        let secret = self.secret_nonces.get_mut(index)?.take()?;
+        let public_nonces = self
+            .public_nonces
+            .iter()
+            .filter_map(|(party_id, nonces)| Some((*party_id, nonces.get(index)?.clone())))
+            .collect();
+
+        Some(PreprocessedNonce {
+            slot: NonceSlot {
+                batch_id: self.batch_id,
+                index: index as u32,
+            },
+            secret,
+            public_nonces,
+        })
+    }
+}
+
+#[derive(ProtocolMessage, Serialize, Deserialize, Clone)]
+pub enum Msg {
+    PreprocessBroadcast(PreprocessMsg),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PreprocessMsg {
+    source: u32,
+    nonces: Vec<PublicNonce>,
+}
+
+pub async fn wsts_preprocessing_protocol<M, R: CryptoRng + RngCore>(
+    network: M,
+    keygen_state: &WstsState,
+    batch_id: u64,
+    count: usize,
+    rng: &mut R,
+) -> Result<NonceBatch, SigningError>
+where
+    M: Mpc<ProtocolMessage = Msg>,
+{
+    let party_state = keygen_state
+        .party_state()
+        .ok_or_else(|| SigningError::ContextError("Party not found".to_string()))?;
+    let signer = Party::load(&party_state);
+
+    let n_signers = keygen_state.n_signers;
+    let MpcParty { delivery, .. } = network.into_party();
+    let (incomings, mut outgoings) = delivery.split();
+
+    let mut rounds = RoundsRouter::builder();
+    let round1 = rounds.add_round(RoundInput::<PreprocessMsg>::broadcast(
+        signer.party_id as _,
+        n_signers as _,
+    ));
+    let mut rounds = rounds.listen(incomings);
+
+    // Generate the batch of nonces, keeping the secret half of each one
+    let mut public_nonces = Vec::with_capacity(count);
+    let mut secret_nonces = Vec::with_capacity(count);
+    for _ in 0..count {
+        let nonce = Nonce::random(rng);
+        public_nonces.push(PublicNonce::from(&nonce));
+        secret_nonces.push(Some(nonce));
+    }
+
+    let my_broadcast = PreprocessMsg {
+        source: signer.party_id,
+        nonces: public_nonces,
+    };
+
+    let msg = Msg::PreprocessBroadcast(my_broadcast.clone());
+    send_message::<M, _>(msg, &mut outgoings).await?;
+
+    let messages = rounds
+        .complete(round1)
+        .await
+        .map_err(|err| SigningError::MpcError(err.to_string()))?;
+
+    let mut batch = NonceBatch {
+        batch_id,
+        public_nonces: BTreeMap::new(),
+        secret_nonces,
+    };
+
+    // Batches are keyed by the party that really sent them rather than by the `source` each
+    // party claims, otherwise a party could replace another party's nonces with its own
+    let mut impostors = Vec::new();
+    batch
+        .public_nonces
+        .insert(signer.party_id, my_broadcast.nonces);
+    for (sender, _, msg) in messages.into_iter_indexed() {
+        let sender = sender as u32;
+        if msg.source != sender {
+            impostors.push(sender);
+            continue;
+        }
+
+        if msg.nonces.len() != count {
+            return Err(SigningError::MpcError(format!(
+                "Party {sender} sent an invalid batch of nonces"
+            )));
+        }
+
+        batch.public_nonces.insert(sender, msg.nonces);
+    }
+
+    if !impostors.is_empty() {
+        return Err(SigningError::Blame {
+            culprits: impostors,
+        });
+    }
+
+    info!(
+        "Party {} preprocessed {count} nonces in batch {batch_id}",
+        signer.party_id
+    );
+
+    Ok(batch)
+}
+
+impl HasRecipient for Msg {
+    fn recipient(&self) -> MessageDestination {
+        match self {
+            Msg::PreprocessBroadcast(_) => MessageDestination::AllParties,
+        }
+    }
+}
+
+pub async fn send_message<M, Msg>(
+    msg: Msg,
+    tx: &mut <<M as Mpc>::Delivery as Delivery<Msg>>::Send,
+) -> Result<(), SigningError>
+where
+    Msg: HasRecipient,
+    M: Mpc<ProtocolMessage = Msg>,
+{
+    let recipient = msg.recipient();
+    let msg = round_based::Outgoing { recipient, msg };
+    tx.send(msg)
+        .await
+        .map_err(|e| SigningError::DeliveryError(e.to_string()))?;
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use crate::context::WstsContext;
+use crate::policy::{caller_pre_processor, MessageSource};
+use crate::signing::{run_signing_session, SigningError, SigningKey};
+use crate::utils::{MessageMode, OutputFormat};
+use bitcoin::hashes::Hash;
+use bitcoin::key::{Secp256k1, TapTweak, XOnlyPublicKey};
+use bitcoin::psbt::{Input, Psbt};
+use bitcoin::secp256k1::{schnorr, Message};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::{taproot, ScriptBuf, TapNodeHash, Witness};
+use blueprint_sdk::event_listeners::tangle::events::TangleEventListener;
+use blueprint_sdk::event_listeners::tangle::services::services_post_processor;
+use blueprint_sdk::job;
+use blueprint_sdk::logging::info;
+use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::services::events::JobCalled;
+use std::collections::BTreeMap;
+
+#[job(
+    id = 4,
+    params(keygen_call_id, psbt),
+    event_listener(
+        listener = TangleEventListener<WstsContext, JobCalled>,
+        pre_processor = caller_pre_processor,
+        post_processor = services_post_processor,
+    ),
+)]
+/// Signs the Taproot key-path inputs of a PSBT that are owned by a previously generated key,
+/// and finalizes them
+///
+/// Every operator computes the BIP341 sighashes from the PSBT itself, so operators only ever
+/// sign transactions they can inspect. An input is owned by the key when its
+/// `tap_internal_key` is the group key and its `witness_utxo` pays to the Taproot output key
+/// for that internal key and the input's `tap_merkle_root`
+///
+/// # Arguments
+/// * `keygen_call_id` - The call id of the keygen job that generated the key
+/// * `psbt` - The PSBT to sign, in the BIP174 binary format
+/// * `context` - The DFNS context containing network and storage configuration
+///
+/// # Returns
+/// Returns the PSBT with every owned input finalized, in the BIP174 binary format
+///
+/// # Errors
+/// Returns an error if:
+/// - The PSBT cannot be parsed, or has no inputs owned by the key
+/// - An owned input's sighash cannot be computed, e.g. because a `witness_utxo` is missing
+/// - Failed to retrieve the key entry
+/// - Signing process failed
+pub async fn sign_psbt(
+    keygen_call_id: u64,
+    psbt: Vec<u8>,
+    context: WstsContext,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut unsigned =
+        Psbt::deserialize(&psbt).map_err(|e| SigningError::InvalidPsbt(e.to_string()))?;
+
+    let key = SigningKey::load(keygen_call_id, &context).await?;
+    let internal_key = XOnlyPublicKey::from_slice(&key.state.public_key_frost_format[1..])
+        .map_err(|_| SigningError::InvalidPublicKey)?;
+
+    // Inputs are signed in one session per script merkle root, since each root gives a
+    // different output key
+    let sighashes = key_spend_sighashes(&unsigned, internal_key)?;
+    if sighashes.is_empty() {
+        return Err(SigningError::InvalidPsbt("no inputs owned by the key".into()).into());
+    }
+
+    let mut sessions: BTreeMap<Option<TapNodeHash>, Vec<KeySpend>> = BTreeMap::new();
+    for spend in sighashes {
+        sessions.entry(spend.merkle_root).or_default().push(spend);
+    }
+
+    let secp = Secp256k1::verification_only();
+    for (merkle_root, spends) in sessions {
+        let merkle_root_bytes = merkle_root.map(|root| root.to_byte_array());
+        let format = OutputFormat::Taproot(merkle_root_bytes);
+        let session_payload =
+            crate::compute_sha256_hash!(&psbt, merkle_root_bytes.unwrap_or_default());
+
+        info!(
+            "Signing {} PSBT input(s) with merkle root {merkle_root:?}",
+            spends.len()
+        );
+
+        let messages = spends.iter().map(|spend| spend.sighash.to_vec()).collect();
+        let output = run_signing_session(
+            &key,
+            messages,
+            MessageSource::PsbtSighashes,
+            &MessageMode::Prehashed,
+            &session_payload,
+            format,
+            &context,
+        )
+        .await?;
+
+        let (output_key, _) = internal_key.tap_tweak(&secp, merkle_root);
+        for (spend, (state, _)) in spends.iter().zip(output) {
+            let signature = schnorr::Signature::from_slice(
+                &format.encode_signature(&state.signature_frost_format),
+            )
+            .map_err(|_| SigningError::InvalidSignature)?;
+
+            // The protocol already verified the signature, but this is the check the network
+            // will make, so make it before handing the transaction back
+            let message = Message::from_digest(spend.sighash);
+            secp.verify_schnorr(&signature, &message, &output_key.to_x_only_public_key())
+                .map_err(|_| SigningError::InvalidSignature)?;
+
+            let signature = taproot::Signature {
+                signature,
+                sighash_type: spend.sighash_type,
+            };
+            finalize_key_spend(&mut unsigned.inputs[spend.index], signature);
+        }
+    }
+
+    Ok(unsigned.serialize())
+}
+
+/// A Taproot key-path input of a PSBT that is owned by the key
+struct KeySpend {
+    index: usize,
+    merkle_root: Option<TapNodeHash>,
+    sighash_type: TapSighashType,
+    sighash: [u8; 32],
+}
+
+/// Computes the BIP341 key-path sighash of every input of `psbt` owned by `internal_key`
+fn key_spend_sighashes(
+    psbt: &Psbt,
+    internal_key: XOnlyPublicKey,
+) -> Result<Vec<KeySpend>, SigningError> {
+    let secp = Secp256k1::verification_only();
+
+    let owned = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| {
+            input.tap_internal_key == Some(internal_key)
+                && input.witness_utxo.as_ref().is_some_and(|utxo| {
+                    utxo.script_pubkey
+                        == ScriptBuf::new_p2tr(&secp, internal_key, input.tap_merkle_root)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    if owned.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // BIP341 sighashes commit to every output being spent, not just the signed one
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                SigningError::InvalidPsbt(format!("input {index} has no witness_utxo"))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let prevouts = Prevouts::All(&prevouts);
+
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    owned
+        .into_iter()
+        .map(|(index, input)| {
+            let sighash_type = input
+                .sighash_type
+                .map(|sighash_type| sighash_type.taproot_hash_ty())
+                .transpose()
+                .map_err(|e| SigningError::InvalidPsbt(format!("input {index}: {e}")))?
+                .unwrap_or(TapSighashType::Default);
+
+            let sighash = cache
+                .taproot_key_spend_signature_hash(index, &prevouts, sighash_type)
+                .map_err(|e| SigningError::InvalidPsbt(format!("input {index}: {e}")))?;
+
+            Ok(KeySpend {
+                index,
+                merkle_root: input.tap_merkle_root,
+                sighash_type,
+                sighash: sighash.to_byte_array(),
+            })
+        })
+        .collect()
+}
+
+/// Finalizes a key-path input with its signature, clearing every field the BIP174 finalizer
+/// removes
+fn finalize_key_spend(input: &mut Input, signature: taproot::Signature) {
+    *input = Input {
+        non_witness_utxo: input.non_witness_utxo.take(),
+        witness_utxo: input.witness_utxo.take(),
+        final_script_witness: Some(Witness::p2tr_key_spend(&signature)),
+        proprietary: std::mem::take(&mut input.proprietary),
+        unknown: std::mem::take(&mut input.unknown),
+        ..Default::default()
+    };
+}
@@ -0,0 +1,131 @@
+use crate::keygen_state_machine::WstsState;
+use crate::preprocessing_state_machine::NoncePool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The version of the stored layout of [`WstsState`] written by this build
+///
+/// # Versions
+/// * 0 - A bare [`WstsState`], from before stored keys were versioned. States from before
+///   nonce preprocessing have no `nonce_pool` or `nonce_ledger`
+/// * 1 - A [`KeyShareEnvelope`] around a [`WstsState`] with every field present
+/// * 2 - As 1, without the `nonce_ledger`, which moved to records of its own in the store
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+/// Upgrades a state from one schema version to the next
+type Migration = fn(Value) -> Result<Value, String>;
+
+/// `MIGRATIONS[v]` upgrades a state from version `v` to version `v + 1`. To change the stored
+/// layout, bump [`CURRENT_SCHEMA_VERSION`] and append the migration from the previous layout
+const MIGRATIONS: [Migration; CURRENT_SCHEMA_VERSION as usize] =
+    [add_nonce_fields, drop_nonce_ledger];
+
+/// The versioned form that key material is stored in
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyShareEnvelope {
+    pub version: u32,
+    pub state: Value,
+}
+
+/// Error type for loading and migrating stored key material
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("Failed to serialize data: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error(
+        "Stored key has schema version {0}, but this build only supports up to version {CURRENT_SCHEMA_VERSION}"
+    )]
+    UnsupportedVersion(u32),
+
+    #[error("Failed to migrate stored key from schema version {version}: {reason}")]
+    MigrationError { version: u32, reason: String },
+}
+
+/// Serializes `state` in the current schema version
+pub fn encode_state(state: &WstsState) -> Result<Vec<u8>, SchemaError> {
+    let envelope = KeyShareEnvelope {
+        version: CURRENT_SCHEMA_VERSION,
+        state: serde_json::to_value(state)?,
+    };
+
+    Ok(serde_json::to_vec(&envelope)?)
+}
+
+/// Deserializes a state stored in any supported schema version, migrating it to the current
+/// one. Returns the state along with the version it was stored in
+pub fn decode_state(bytes: &[u8]) -> Result<(WstsState, u32), SchemaError> {
+    let envelope = read_envelope(serde_json::from_slice(bytes)?)?;
+    let version = envelope.version;
+    let state = migrate(envelope)?;
+
+    Ok((serde_json::from_value(state)?, version))
+}
+
+/// Reads the envelope of stored key material, treating anything that isn't one as a bare
+/// version 0 state
+fn read_envelope(value: Value) -> Result<KeyShareEnvelope, SchemaError> {
+    let is_envelope = value
+        .as_object()
+        .is_some_and(|object| object.contains_key("version") && object.contains_key("state"));
+
+    if is_envelope {
+        Ok(serde_json::from_value(value)?)
+    } else {
+        Ok(KeyShareEnvelope {
+            version: 0,
+            state: value,
+        })
+    }
+}
+
+/// Applies every migration from the envelope's version to [`CURRENT_SCHEMA_VERSION`]
+fn migrate(envelope: KeyShareEnvelope) -> Result<Value, SchemaError> {
+    if envelope.version > CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion(envelope.version));
+    }
+
+    let mut state = envelope.state;
+    for (version, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(envelope.version as usize)
+    {
+        state = migration(state).map_err(|reason| SchemaError::MigrationError {
+            version: version as u32,
+            reason,
+        })?;
+    }
+
+    Ok(state)
+}
+
+/// 0 -> 1: Fills in the nonce pool and ledger of states from before nonce preprocessing, which
+/// used to be left to serde defaults
+fn add_nonce_fields(mut state: Value) -> Result<Value, String> {
+    let object = state
+        .as_object_mut()
+        .ok_or_else(|| "state is not an object".to_string())?;
+
+    if !object.contains_key("nonce_pool") {
+        let nonce_pool = serde_json::to_value(NoncePool::default()).map_err(|e| e.to_string())?;
+        object.insert("nonce_pool".to_string(), nonce_pool);
+    }
+    object
+        .entry("nonce_ledger")
+        .or_insert_with(|| Value::Object(Default::default()));
+
+    Ok(state)
+}
+
+/// 1 -> 2: Drops the nonce ledger. Its entries only guard sessions that were running when the
+/// node stopped, so upgrading with no signing sessions running loses nothing
+fn drop_nonce_ledger(mut state: Value) -> Result<Value, String> {
+    state
+        .as_object_mut()
+        .ok_or_else(|| "state is not an object".to_string())?
+        .remove("nonce_ledger");
+
+    Ok(state)
+}
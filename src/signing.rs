@@ -1,16 +1,20 @@
 use crate::context::WstsContext;
+use crate::keygen_state_machine::WstsState;
+use crate::policy::{caller_pre_processor, MessageSource, SigningRefusal};
+use crate::signing_state_machine::WstsSigningState;
+use crate::transcript::SigningTranscript;
+use crate::utils::{MessageMode, OutputFormat};
 use blueprint_sdk::crypto::k256::K256VerifyingKey;
 use blueprint_sdk::crypto::KeyEncoding;
 use blueprint_sdk::event_listeners::tangle::events::TangleEventListener;
-use blueprint_sdk::event_listeners::tangle::services::{
-    services_post_processor, services_pre_processor,
-};
+use blueprint_sdk::event_listeners::tangle::services::services_post_processor;
 use blueprint_sdk::logging::info;
 use blueprint_sdk::macros::ext::contexts::tangle::TangleClientContext;
 use blueprint_sdk::networking::round_based_compat::NetworkDeliveryWrapper;
 use blueprint_sdk::tangle_subxt::tangle_testnet_runtime::api::services::events::JobCalled;
 use blueprint_sdk::{job, macros as gadget_macros};
 use gadget_macros::ext::clients::GadgetServicesClient;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 /// Configuration constants for the WSTS signing process
@@ -18,10 +22,17 @@ const SIGNING_SALT: &str = "wsts-signing";
 
 #[job(
     id = 1,
-    params(keygen_call_id, message),
+    params(
+        keygen_call_id,
+        message,
+        output_format,
+        taproot_merkle_root,
+        message_mode,
+        domain_tag
+    ),
     event_listener(
         listener = TangleEventListener<WstsContext, JobCalled>,
-        pre_processor = services_pre_processor,
+        pre_processor = caller_pre_processor,
         post_processor = services_post_processor,
     ),
 )]
@@ -29,10 +40,19 @@ const SIGNING_SALT: &str = "wsts-signing";
 ///
 /// # Arguments
 /// * `message` - The message to sign as a byte vector
+/// * `output_format` - How to encode the signature: 0 for the 65-byte FROST format, 1 for a
+///   64-byte BIP340 signature, 2 for a 64-byte BIP340 signature for the BIP341 Taproot output
+///   key
+/// * `taproot_merkle_root` - The script merkle root the Taproot output key was tweaked with,
+///   or empty for a key-path-only output. Must be empty unless `output_format` is 2
+/// * `message_mode` - What to sign: 0 for the raw message, 1 for a 32-byte digest the caller
+///   already computed, 2 for the BIP340 tagged hash of the message with `domain_tag`
+/// * `domain_tag` - The tag to hash the message with. Must be empty unless `message_mode` is 2
 /// * `context` - The DFNS context containing network and storage configuration
 ///
 /// # Returns
-/// Returns the signature as a byte vector on success
+/// Returns the signature as a byte vector on success. [`sign_with_details`] also returns what
+/// was signed
 ///
 /// # Errors
 /// Returns an error if:
@@ -42,73 +62,461 @@ const SIGNING_SALT: &str = "wsts-signing";
 pub async fn sign(
     keygen_call_id: u64,
     message: Vec<u8>,
+    output_format: u8,
+    taproot_merkle_root: Vec<u8>,
+    message_mode: u8,
+    domain_tag: Vec<u8>,
     context: WstsContext,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // let message = message.into_bytes();
-    // Get configuration and compute deterministic values
-    let client = context.tangle_client().await?;
-    let blueprint_id = client
-        .blueprint_id()
-        .await
-        .map_err(|e| SigningError::ContextError(e.to_string()))?;
-
-    let call_id = context
-        .call_id
-        .ok_or_else(|| SigningError::ContextError("call_id not set".into()))?;
-
-    // Setup party information
-    let (i, operators) = client
-        .get_party_index_and_operators()
-        .await
-        .map_err(|e| SigningError::ContextError(e.to_string()))?;
-
-    let parties: BTreeMap<u16, _> = operators
+    let output = sign_message(
+        keygen_call_id,
+        message,
+        output_format,
+        taproot_merkle_root,
+        message_mode,
+        domain_tag,
+        context,
+    )
+    .await?;
+
+    Ok(output.signature)
+}
+
+#[job(
+    id = 5,
+    params(
+        keygen_call_id,
+        message,
+        output_format,
+        taproot_merkle_root,
+        message_mode,
+        domain_tag
+    ),
+    event_listener(
+        listener = TangleEventListener<WstsContext, JobCalled>,
+        pre_processor = caller_pre_processor,
+        post_processor = services_post_processor,
+    ),
+)]
+/// Signs a message like [`sign`], returning how the message was signed along with the
+/// signature
+///
+/// # Arguments
+/// * `message` - The message to sign as a byte vector
+/// * `output_format` - How to encode the signature, as for [`sign`]
+/// * `taproot_merkle_root` - The Taproot script merkle root, as for [`sign`]
+/// * `message_mode` - What to sign, as for [`sign`]
+/// * `domain_tag` - The tag to hash the message with, as for [`sign`]
+/// * `context` - The DFNS context containing network and storage configuration
+///
+/// # Returns
+/// Returns the signature on success, along with the message mode, the exact bytes that were
+/// signed and the digest of the signing transcript
+///
+/// # Errors
+/// Returns an error if:
+/// - Failed to retrieve blueprint ID or call ID
+/// - Failed to retrieve the key entry
+/// - Signing process failed
+pub async fn sign_with_details(
+    keygen_call_id: u64,
+    message: Vec<u8>,
+    output_format: u8,
+    taproot_merkle_root: Vec<u8>,
+    message_mode: u8,
+    domain_tag: Vec<u8>,
+    context: WstsContext,
+) -> Result<SignatureOutput, Box<dyn std::error::Error>> {
+    sign_message(
+        keygen_call_id,
+        message,
+        output_format,
+        taproot_merkle_root,
+        message_mode,
+        domain_tag,
+        context,
+    )
+    .await
+}
+
+/// Signs a single message for [`sign`] and [`sign_with_details`]
+async fn sign_message(
+    keygen_call_id: u64,
+    message: Vec<u8>,
+    output_format: u8,
+    taproot_merkle_root: Vec<u8>,
+    message_mode: u8,
+    domain_tag: Vec<u8>,
+    context: WstsContext,
+) -> Result<SignatureOutput, Box<dyn std::error::Error>> {
+    let format = OutputFormat::new(output_format, &taproot_merkle_root)
+        .map_err(SigningError::ContextError)?;
+    let mode = MessageMode::new(message_mode, domain_tag).map_err(SigningError::ContextError)?;
+
+    let mut output = signing_session(
+        keygen_call_id,
+        vec![message.clone()],
+        &mode,
+        &message,
+        format,
+        context,
+    )
+    .await?;
+
+    Ok(SignatureOutput::new(output.remove(0), format, &mode))
+}
+
+#[job(
+    id = 3,
+    params(
+        keygen_call_id,
+        messages,
+        output_format,
+        taproot_merkle_root,
+        message_mode,
+        domain_tag
+    ),
+    event_listener(
+        listener = TangleEventListener<WstsContext, JobCalled>,
+        pre_processor = caller_pre_processor,
+        post_processor = services_post_processor,
+    ),
+)]
+/// Signs a batch of messages using the WSTS protocol with a previously generated key, in a
+/// single session with one nonce round and one signature share round
+///
+/// # Arguments
+/// * `messages` - The messages to sign
+/// * `output_format` - How to encode the signatures, as for [`sign`]
+/// * `taproot_merkle_root` - The Taproot script merkle root, as for [`sign`]
+/// * `message_mode` - What to sign for each message, as for [`sign`]
+/// * `domain_tag` - The tag to hash the messages with, as for [`sign`]
+/// * `context` - The DFNS context containing network and storage configuration
+///
+/// # Returns
+/// Returns the signatures as for [`sign_with_details`], in the same order as `messages`
+///
+/// # Errors
+/// Returns an error if:
+/// - `messages` is empty
+/// - Failed to retrieve blueprint ID or call ID
+/// - Failed to retrieve the key entry
+/// - Signing process failed
+pub async fn sign_batch(
+    keygen_call_id: u64,
+    messages: Vec<Vec<u8>>,
+    output_format: u8,
+    taproot_merkle_root: Vec<u8>,
+    message_mode: u8,
+    domain_tag: Vec<u8>,
+    context: WstsContext,
+) -> Result<Vec<SignatureOutput>, Box<dyn std::error::Error>> {
+    let format = OutputFormat::new(output_format, &taproot_merkle_root)
+        .map_err(SigningError::ContextError)?;
+    let mode = MessageMode::new(message_mode, domain_tag).map_err(SigningError::ContextError)?;
+
+    // Hash each message separately, so that different batches can never produce the same
+    // session payload
+    let session_payload = messages
+        .iter()
+        .flat_map(|message| crate::compute_sha256_hash!(message))
+        .collect::<Vec<u8>>();
+
+    let output = signing_session(
+        keygen_call_id,
+        messages,
+        &mode,
+        &session_payload,
+        format,
+        context,
+    )
+    .await?;
+
+    Ok(output
         .into_iter()
-        .enumerate()
-        .map(|(j, (_, ecdsa))| {
-            (
-                j as u16,
-                K256VerifyingKey::from_bytes(&ecdsa.0).expect("33 byte compressed ECDSA key"),
-            )
+        .map(|signed| SignatureOutput::new(signed, format, &mode))
+        .collect())
+}
+
+/// The result of signing a message, recording how the message was signed so that verifiers
+/// know what the signature is over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureOutput {
+    /// The signature, in the requested output format
+    pub signature: Vec<u8>,
+    /// The `message_mode` the message was signed with
+    pub message_mode: u8,
+    /// The domain tag the message was hashed with, empty unless `message_mode` is 2
+    pub domain_tag: Vec<u8>,
+    /// The exact bytes that were signed, which differ from the message when it was hashed
+    pub signed_message: Vec<u8>,
+    /// The digest of the [`SigningTranscript`], which every honest signer agrees on. Each
+    /// operator stores the full transcript under this digest
+    pub transcript_digest: Vec<u8>,
+}
+
+impl SignatureOutput {
+    fn new(
+        (state, transcript): (WstsSigningState, SigningTranscript),
+        format: OutputFormat,
+        mode: &MessageMode,
+    ) -> Self {
+        SignatureOutput {
+            signature: format.encode_signature(&state.signature_frost_format),
+            message_mode: mode.id(),
+            domain_tag: mode.domain_tag().to_vec(),
+            signed_message: state.message,
+            transcript_digest: transcript.digest().to_vec(),
+        }
+    }
+}
+
+/// A stored key along with everything needed to run signing sessions with it
+pub(crate) struct SigningKey {
+    /// This operator's index in `parties`
+    i: u16,
+    parties: BTreeMap<u16, K256VerifyingKey>,
+    keygen_call_id: u64,
+    /// The call id of the job that is signing
+    call_id: u64,
+    key_execution_hash: [u8; 32],
+    store_key: String,
+    pub(crate) state: WstsState,
+}
+
+impl SigningKey {
+    /// Loads the key generated by the given keygen job
+    pub(crate) async fn load(
+        keygen_call_id: u64,
+        context: &WstsContext,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Get configuration and compute deterministic values
+        let client = context.tangle_client().await?;
+        let blueprint_id = client
+            .blueprint_id()
+            .await
+            .map_err(|e| SigningError::ContextError(e.to_string()))?;
+
+        let call_id = context
+            .call_id
+            .ok_or_else(|| SigningError::ContextError("call_id not set".into()))?;
+
+        // Setup party information
+        let (i, operators) = client
+            .get_party_index_and_operators()
+            .await
+            .map_err(|e| SigningError::ContextError(e.to_string()))?;
+
+        let parties: BTreeMap<u16, _> = operators
+            .into_iter()
+            .enumerate()
+            .map(|(j, (_, ecdsa))| {
+                (
+                    j as u16,
+                    K256VerifyingKey::from_bytes(&ecdsa.0).expect("33 byte compressed ECDSA key"),
+                )
+            })
+            .collect();
+
+        let n = parties.len() as u16;
+
+        // Compute hash for key retrieval. Must use the call_id of the keygen job
+        let (_, key_execution_hash) =
+            crate::compute_execution_hashes(n, blueprint_id, keygen_call_id, SIGNING_SALT);
+
+        // Retrieve the key entry
+        let store_key = context
+            .key_id(keygen_call_id)
+            .map_err(|e| SigningError::ContextError(e.to_string()))?
+            .store_key();
+        let state = context
+            .store
+            .get(&store_key)
+            .map_err(|e| SigningError::ContextError(e.to_string()))?
+            .ok_or_else(|| SigningError::ContextError("Key entry not found".to_string()))?;
+
+        Ok(SigningKey {
+            i: i as u16,
+            keygen_call_id,
+            parties,
+            call_id,
+            key_execution_hash,
+            store_key,
+            state,
         })
-        .collect();
+    }
+}
+
+/// Runs a signing session over `messages` with the key from the given keygen job, returning
+/// the signing state and transcript of each message in order. The messages are as given to
+/// the job, and `mode` turns each of them into the bytes that are signed. `session_payload`
+/// identifies the messages in the session id
+async fn signing_session(
+    keygen_call_id: u64,
+    messages: Vec<Vec<u8>>,
+    mode: &MessageMode,
+    session_payload: &[u8],
+    format: OutputFormat,
+    context: WstsContext,
+) -> Result<Vec<(WstsSigningState, SigningTranscript)>, Box<dyn std::error::Error>> {
+    let key = SigningKey::load(keygen_call_id, &context).await?;
+    run_signing_session(
+        &key,
+        messages,
+        MessageSource::Caller,
+        mode,
+        session_payload,
+        format,
+        &context,
+    )
+    .await
+}
 
-    let n = parties.len() as u16;
-    let i = i as u16;
+/// Runs a signing session over `messages` with an already loaded key. See [`signing_session`].
+/// `source` decides which rules of the signing policy the messages are checked against
+pub(crate) async fn run_signing_session(
+    key: &SigningKey,
+    messages: Vec<Vec<u8>>,
+    source: MessageSource,
+    mode: &MessageMode,
+    session_payload: &[u8],
+    format: OutputFormat,
+    context: &WstsContext,
+) -> Result<Vec<(WstsSigningState, SigningTranscript)>, Box<dyn std::error::Error>> {
+    if messages.is_empty() {
+        return Err(SigningError::ContextError("No messages to sign".into()).into());
+    }
 
-    // Compute hash for key retrieval. Must use the call_id of the keygen job
-    let (meta_hash, deterministic_hash) =
-        crate::compute_execution_hashes(n, blueprint_id, keygen_call_id, SIGNING_SALT);
+    let signed_messages = messages
+        .iter()
+        .map(|message| mode.signed_message(message))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SigningError::ContextError)?;
 
-    // Retrieve the key entry
-    let store_key = hex::encode(meta_hash);
-    let state = context
-        .store
-        .get(&store_key)
-        .ok_or_else(|| SigningError::ContextError("Key entry not found".to_string()))?;
+    let i = key.i;
+    let n = key.parties.len();
+    let store_key = &key.store_key;
 
-    info!(
-        "Starting WSTS Signing for party {i}, n={n}, eid={}",
-        hex::encode(deterministic_hash)
-    );
+    // The session itself is unique to this signing job, so concurrent signing jobs against the
+    // same key never share messages
+    let deterministic_hash =
+        crate::compute_session_hash(key.key_execution_hash, key.call_id, session_payload);
+
+    let _session = context
+        .start_signing_session(deterministic_hash)
+        .ok_or_else(|| SigningError::ContextError("Signing session already running".into()))?;
 
     let network = NetworkDeliveryWrapper::new(
         context.network_backend.clone(),
         i,
         deterministic_hash,
-        parties.clone(),
+        key.parties.clone(),
     );
 
-    let mut rng = rand::rngs::OsRng;
-
     let network = round_based::party::MpcParty::connected(network);
 
-    let output =
-        crate::signing_state_machine::wsts_signing_protocol(network, &state, message, &mut rng)
+    // The coordinator picks the nonces and signers of the session. It rotates with the call id,
+    // so that a job retried because the coordinator was offline gets another one
+    let coordinator = (key.call_id % n as u64) as u32;
+
+    // Check the request against our signing policy before taking part, and tell the other
+    // parties if we won't rather than leaving them to time out. The policy sees the messages
+    // as given to the job, since their prefixes and lengths are lost once they are hashed
+    let caller = context.caller.as_ref();
+    let checked = match source {
+        MessageSource::Caller => context.policy.check(store_key, caller, mode, &messages),
+        MessageSource::PsbtSighashes => context.policy.check_psbt(store_key, caller, &messages),
+    };
+    let permit = match checked {
+        Ok(permit) => permit,
+        Err(violation) => {
+            info!(
+                "Refusing WSTS Signing for party {i}, eid={}: {violation}",
+                hex::encode(deterministic_hash)
+            );
+
+            let refusal = SigningRefusal::new(
+                &context.identity,
+                i as u32,
+                deterministic_hash,
+                violation.to_string(),
+            )?;
+            crate::signing_state_machine::wsts_refusal_protocol(
+                network,
+                &key.state,
+                refusal,
+                coordinator,
+                &context.nonce_pool(store_key),
+            )
             .await?;
 
-    let signature_frost_format = output.signature_frost_format.clone();
-    Ok(signature_frost_format)
+            return Err(SigningError::PolicyViolation(violation.to_string()).into());
+        }
+    };
+
+    info!(
+        "Starting WSTS Signing of {} message(s) for party {i}, n={n}, eid={}",
+        messages.len(),
+        hex::encode(deterministic_hash)
+    );
+
+    let mut rng = rand::rngs::OsRng;
+
+    let ledger = context.nonce_ledger(store_key, deterministic_hash);
+    let output = crate::signing_state_machine::wsts_signing_protocol(
+        network,
+        &key.state,
+        signed_messages,
+        format,
+        coordinator,
+        &context.nonce_pool(store_key),
+        &ledger,
+        &mut rng,
+    )
+    .await;
+    ledger.clear();
+    let output = output.map_err(|err| verify_refusals(err, key, deterministic_hash))?;
+
+    // Only messages that were signed count towards the rate limit
+    permit.charge();
+
+    // Keep a transcript of every signature for audits and disputes
+    let output = output
+        .into_iter()
+        .map(|state| {
+            let transcript = SigningTranscript::new(key.keygen_call_id, key.call_id, &state);
+            context
+                .transcripts
+                .set(&hex::encode(transcript.digest()), transcript.clone());
+            (state, transcript)
+        })
+        .collect();
+
+    Ok(output)
+}
+
+/// Checks the signatures on the refusals that aborted a session, so that only refusals the
+/// parties can be held to are reported
+fn verify_refusals(err: SigningError, key: &SigningKey, session_id: [u8; 32]) -> SigningError {
+    let SigningError::Refused { refusals } = &err else {
+        return err;
+    };
+
+    let forged = refusals.iter().find(|refusal| {
+        refusal.session_id != session_id
+            || !key
+                .parties
+                .get(&(refusal.party_id as u16))
+                .is_some_and(|public_key| refusal.verify(public_key))
+    });
+
+    match forged {
+        Some(refusal) => SigningError::MpcError(format!(
+            "Party {} sent a refusal with an invalid signature",
+            refusal.party_id
+        )),
+        None => err,
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -139,4 +547,40 @@ pub enum SigningError {
 
     #[error("Invalid FROST verification")]
     InvalidFrostVerification,
+
+    #[error("Refusing to reuse a nonce: {0}")]
+    NonceReuse(String),
+
+    #[error("Invalid PSBT: {0}")]
+    InvalidPsbt(String),
+
+    #[error("Signing request rejected by the signing policy: {0}")]
+    PolicyViolation(String),
+
+    /// Too many parties refused the session for the threshold to be met
+    #[error("Signing refused by {}", format_refusals(refusals))]
+    Refused { refusals: Vec<SigningRefusal> },
+
+    /// The listed parties sent signature shares that do not verify against their nonces and
+    /// public key shares
+    #[error("Signing aborted: invalid signature shares from parties {culprits:?}")]
+    Blame { culprits: Vec<u32> },
+}
+
+fn format_refusals(refusals: &[SigningRefusal]) -> String {
+    refusals
+        .iter()
+        .map(|refusal| format!("party {} ({})", refusal.party_id, refusal.reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl SigningError {
+    /// Returns the parties blamed for the failure, if the failure is attributable
+    pub fn culprits(&self) -> Option<&[u32]> {
+        match self {
+            SigningError::Blame { culprits } => Some(culprits),
+            _ => None,
+        }
+    }
 }
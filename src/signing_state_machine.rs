@@ -1,20 +1,26 @@
 use rand::{CryptoRng, RngCore};
 use round_based::{
-    rounds_router::{simple_store::RoundInput, RoundsRouter},
-    Delivery, MessageDestination, Mpc, MpcParty, ProtocolMessage,
+    rounds_router::{simple_store::RoundInput, MessagesStore, RoundsRouter},
+    Delivery, Incoming, MessageDestination, MessageType, Mpc, MpcParty, ProtocolMessage,
 };
-use std::collections::HashMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use crate::keygen_state_machine::{HasRecipient, WstsState};
+use crate::policy::SigningRefusal;
+use crate::preprocessing_state_machine::{same_nonce, NonceSlot, NonceStatus, PreprocessedNonce};
 use crate::signing::SigningError;
+use crate::utils::{OutputFormat, SignatureKey};
+use blueprint_sdk::logging::{info, warn};
 use frost_secp256k1_tr::{Ciphersuite, Secp256K1Sha256TR, VerifyingKey};
 use itertools::Itertools;
-use p256k1::point::Point;
+use p256k1::point::{Point, G};
 use p256k1::scalar::Scalar;
 use round_based::SinkExt;
 use serde::{Deserialize, Serialize};
-use wsts::common::Signature;
+use wsts::common::{Nonce, Signature};
+use wsts::compute;
 use wsts::v2::Party;
 use wsts::{
     common::{PublicNonce, SignatureShare},
@@ -79,6 +85,7 @@ impl WstsSigningState {
 #[allow(clippy::large_enum_variant)]
 pub enum Msg {
     Round1(Round1Msg),
+    SignerSet(SignerSetMsg),
     Round2(Round2Msg),
 }
 
@@ -86,142 +93,646 @@ pub enum Msg {
 pub struct Round1Msg {
     source: u32,
     key_ids: Vec<u32>,
-    nonce: PublicNonce,
+    nonces: Vec<PublicNonce>,
+    /// Set, with no nonces, when the sender's signing policy rejected the session
+    refusal: Option<SigningRefusal>,
+}
+
+/// The coordinator's choice of nonces for a session. Every party signs with exactly these
+/// nonces, so all signers agree on the signer set before any share is produced
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignerSetMsg {
+    source: u32,
+    /// The signers and the fresh public nonces each of them sent the coordinator in round 1
+    nonces: BTreeMap<u32, Vec<PublicNonce>>,
+    /// The preprocessed nonces to sign with instead, one per message, in which case every
+    /// party signs
+    nonce_slots: Vec<NonceSlot>,
+    /// Set, with no nonces, when so many parties refused the session, possibly the
+    /// coordinator itself, that the threshold can not be met
+    refusals: Vec<SigningRefusal>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Round2Msg {
     source: u32,
-    signature_share: SignatureShare,
+    signers: Vec<u32>,
+    nonce_slots: Vec<NonceSlot>,
+    signature_shares: Vec<SignatureShare>,
+    /// Set, with no signature shares, when the sender's signing policy rejected a session
+    /// that uses preprocessed nonces
+    refusal: Option<SigningRefusal>,
+}
+
+/// Implemented by protocol messages that a party can send to refuse a signing session
+pub trait Refusable {
+    fn refusal(&self) -> Option<&SigningRefusal>;
+}
+
+impl Refusable for Round1Msg {
+    fn refusal(&self) -> Option<&SigningRefusal> {
+        self.refusal.as_ref()
+    }
+}
+
+impl Refusable for Round2Msg {
+    fn refusal(&self) -> Option<&SigningRefusal> {
+        self.refusal.as_ref()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoundInputError {
+    #[error("Expected a broadcast message from party {0}")]
+    NotBroadcast(u16),
+
+    #[error("Received a message from unknown party {0}")]
+    UnknownSender(u16),
+
+    #[error("Party {0} sent more than one message in the same round")]
+    AttemptToOverwrite(u16),
+
+    #[error("Party {0} sent a refusal on behalf of another party")]
+    ForgedRefusal(u16),
+
+    #[error("Party {0} sent a signer set, but is not the coordinator")]
+    NotCoordinator(u16),
+}
+
+/// Checks that a refusal, if the message is one, was made by the party that sent it
+fn check_refusal<M: Refusable>(msg: &Incoming<M>) -> Result<(), RoundInputError> {
+    match msg.msg.refusal() {
+        Some(refusal) if refusal.party_id != msg.sender as u32 => {
+            Err(RoundInputError::ForgedRefusal(msg.sender))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Collects round 1 messages until the senders, counting ourselves, own at least `threshold`
+/// key ids between them. Unlike [`RoundInput`](round_based::rounds_router::simple_store::RoundInput)
+/// it does not wait for every party from keygen, so only a threshold of them need to be online.
+/// Parties that refuse the session do not count towards the threshold, and once so many have
+/// refused that it can no longer be met, the round completes without it. Which parties make
+/// it in depends on the order messages arrive in, so only the coordinator completes this round
+/// and everyone else signs with the set it picks
+pub struct ThresholdRoundInput<M> {
+    key_weights: HashMap<u16, usize>,
+    threshold: usize,
+    weight: usize,
+    refused_weight: usize,
+    messages: BTreeMap<u16, M>,
+}
+
+impl<M> ThresholdRoundInput<M> {
+    pub fn new(party_index: u16, key_weights: HashMap<u16, usize>, threshold: usize) -> Self {
+        let weight = key_weights.get(&party_index).copied().unwrap_or_default();
+        ThresholdRoundInput {
+            key_weights,
+            threshold,
+            weight,
+            refused_weight: 0,
+            messages: BTreeMap::new(),
+        }
+    }
 }
 
+impl<M: Refusable + 'static> MessagesStore for ThresholdRoundInput<M> {
+    type Msg = M;
+    type Output = BTreeMap<u16, M>;
+    type Error = RoundInputError;
+
+    fn add_message(&mut self, msg: Incoming<M>) -> Result<(), Self::Error> {
+        if msg.msg_type != MessageType::Broadcast {
+            return Err(RoundInputError::NotBroadcast(msg.sender));
+        }
+
+        let weight = *self
+            .key_weights
+            .get(&msg.sender)
+            .ok_or(RoundInputError::UnknownSender(msg.sender))?;
+        check_refusal(&msg)?;
+
+        match self.messages.entry(msg.sender) {
+            Entry::Occupied(_) => Err(RoundInputError::AttemptToOverwrite(msg.sender)),
+            Entry::Vacant(entry) => {
+                if msg.msg.refusal().is_some() {
+                    self.refused_weight += weight;
+                } else {
+                    self.weight += weight;
+                }
+                entry.insert(msg.msg);
+                Ok(())
+            }
+        }
+    }
+
+    fn wants_more(&self) -> bool {
+        let total_weight: usize = self.key_weights.values().sum();
+        self.weight < self.threshold && total_weight - self.refused_weight >= self.threshold
+    }
+
+    fn output(self) -> Result<Self::Output, Self> {
+        if self.wants_more() {
+            Err(self)
+        } else {
+            Ok(self.messages)
+        }
+    }
+}
+
+/// Collects the coordinator's [`SignerSetMsg`]. A signer set from any other party is an error
+pub struct CoordinatorRoundInput {
+    coordinator: u16,
+    msg: Option<SignerSetMsg>,
+}
+
+impl CoordinatorRoundInput {
+    pub fn new(coordinator: u16) -> Self {
+        CoordinatorRoundInput {
+            coordinator,
+            msg: None,
+        }
+    }
+}
+
+impl MessagesStore for CoordinatorRoundInput {
+    type Msg = SignerSetMsg;
+    type Output = SignerSetMsg;
+    type Error = RoundInputError;
+
+    fn add_message(&mut self, msg: Incoming<SignerSetMsg>) -> Result<(), Self::Error> {
+        if msg.msg_type != MessageType::Broadcast {
+            return Err(RoundInputError::NotBroadcast(msg.sender));
+        }
+
+        if msg.sender != self.coordinator {
+            return Err(RoundInputError::NotCoordinator(msg.sender));
+        }
+
+        if self.msg.is_some() {
+            return Err(RoundInputError::AttemptToOverwrite(msg.sender));
+        }
+
+        self.msg = Some(msg.msg);
+        Ok(())
+    }
+
+    fn wants_more(&self) -> bool {
+        self.msg.is_none()
+    }
+
+    fn output(self) -> Result<Self::Output, Self> {
+        match self.msg {
+            Some(msg) => Ok(msg),
+            None => Err(self),
+        }
+    }
+}
+
+/// Collects round 2 signature shares, each tagged with the signer set it was produced for,
+/// until every member of the coordinator's signer set, other than us, has sent a share for
+/// that set. Only sets containing the coordinator count, and it only signs for the set it
+/// picked, so no other party can pass off a set of its own. We need not be a signer
+/// ourselves, in which case we only aggregate the shares. A refusal ends the round right away,
+/// since refusals are only sent in this round when every party is a signer
+pub struct SignerSetRoundInput {
+    party_id: u32,
+    coordinator: u32,
+    shares: BTreeMap<u32, Round2Msg>,
+    refusals: Vec<SigningRefusal>,
+}
+
+/// The outcome of a [`SignerSetRoundInput`]
+pub enum SignerSetOutput {
+    /// The signer set and every signer's shares, other than our own if we are a signer
+    Shares(Vec<u32>, BTreeMap<u32, Round2Msg>),
+    Refused(Vec<SigningRefusal>),
+}
+
+impl SignerSetRoundInput {
+    pub fn new(party_id: u32, coordinator: u32) -> Self {
+        SignerSetRoundInput {
+            party_id,
+            coordinator,
+            shares: BTreeMap::new(),
+            refusals: Vec::new(),
+        }
+    }
+
+    fn complete_set(&self) -> Option<&Vec<u32>> {
+        self.shares
+            .values()
+            .map(|msg| &msg.signers)
+            .find(|signers| {
+                signers.contains(&self.coordinator)
+                    && signers.iter().filter(|id| **id != self.party_id).all(|id| {
+                        self.shares
+                            .get(id)
+                            .is_some_and(|msg| &msg.signers == *signers)
+                    })
+            })
+    }
+}
+
+impl MessagesStore for SignerSetRoundInput {
+    type Msg = Round2Msg;
+    type Output = SignerSetOutput;
+    type Error = RoundInputError;
+
+    fn add_message(&mut self, msg: Incoming<Round2Msg>) -> Result<(), Self::Error> {
+        if msg.msg_type != MessageType::Broadcast {
+            return Err(RoundInputError::NotBroadcast(msg.sender));
+        }
+        check_refusal(&msg)?;
+
+        if let Some(refusal) = msg.msg.refusal() {
+            self.refusals.push(refusal.clone());
+            return Ok(());
+        }
+
+        match self.shares.entry(msg.sender as u32) {
+            Entry::Occupied(_) => Err(RoundInputError::AttemptToOverwrite(msg.sender)),
+            Entry::Vacant(entry) => {
+                entry.insert(msg.msg);
+                Ok(())
+            }
+        }
+    }
+
+    fn wants_more(&self) -> bool {
+        self.refusals.is_empty() && self.complete_set().is_none()
+    }
+
+    fn output(self) -> Result<Self::Output, Self> {
+        if !self.refusals.is_empty() {
+            return Ok(SignerSetOutput::Refused(self.refusals));
+        }
+
+        let Some(signers) = self.complete_set().cloned() else {
+            return Err(self);
+        };
+
+        let shares = self
+            .shares
+            .into_iter()
+            .filter(|(party_id, _)| signers.contains(party_id))
+            .collect();
+
+        Ok(SignerSetOutput::Shares(signers, shares))
+    }
+}
+
+/// Persists where each of a signing session's nonces is in its lifecycle, so that it survives
+/// restarts. Every transition is written before the protocol acts on it
+pub trait NonceLedger {
+    /// Moves the session's nonces, one per message, to `status`, failing if that could lead
+    /// to any of them being used twice
+    fn advance(
+        &self,
+        public_nonces: &[PublicNonce],
+        status: NonceStatus,
+    ) -> Result<(), SigningError>;
+}
+
+/// Hands out the preprocessed nonces of the key a session signs with. Taken nonces must be
+/// persisted as used before they are returned
+pub trait PreprocessedNonces {
+    /// Takes the next `count` nonces, or none at all if fewer than `count` are left
+    fn take_next(&self, count: usize) -> Vec<PreprocessedNonce>;
+
+    /// Takes exactly the nonces in `slots`, or none at all if any of them is missing
+    fn take_slots(&self, slots: &[NonceSlot]) -> Option<Vec<PreprocessedNonce>>;
+}
+
+/// Signs every message in `messages` in a single session, running one nonce round and one
+/// signature share round no matter how many messages there are. Each message gets its own
+/// nonce, either freshly generated or preprocessed. The `coordinator` decides which: if its
+/// `pool` has a preprocessed nonce for every message it names them and every party signs with
+/// exactly those, otherwise it picks the signers from the first parties to send it fresh
+/// nonces. Every other party waits for its choice. The signatures are produced for the given
+/// `format`, and are checked against the key it gives for the group key, which for
+/// [`OutputFormat::Taproot`] is the tweaked output key
+///
+/// Preprocessed nonces were exchanged by every party from keygen, so a session that uses them
+/// needs every party to sign. It fails if any of them is offline or no longer holds the named
+/// nonces, and does not fall back to fresh nonces
+#[allow(clippy::too_many_arguments)]
 pub async fn wsts_signing_protocol<M, R: CryptoRng + RngCore>(
     network: M,
     keygen_state: &WstsState,
-    message: Vec<u8>,
+    messages: Vec<Vec<u8>>,
+    format: OutputFormat,
+    coordinator: u32,
+    pool: &impl PreprocessedNonces,
+    ledger: &impl NonceLedger,
     rng: &mut R,
-) -> Result<WstsSigningState, SigningError>
+) -> Result<Vec<WstsSigningState>, SigningError>
 where
     M: Mpc<ProtocolMessage = Msg>,
 {
-    let (mut signer, threshold) = {
-        let lock = keygen_state.party.lock();
-        let state = lock
-            .as_ref()
-            .ok_or_else(|| SigningError::ContextError("Party not found".to_string()))?;
-        let threshold = state.threshold;
-        let signer = Party::load(state);
-        drop(lock);
-        (signer, threshold)
-    };
+    if messages.is_empty() {
+        return Err(SigningError::ContextError(
+            "No messages to sign".to_string(),
+        ));
+    }
+
+    // The secret nonces only ever live in this session
+    let party_state = keygen_state
+        .party_state()
+        .ok_or_else(|| SigningError::ContextError("Party not found".to_string()))?;
+    let threshold = party_state.threshold;
+    let signer = Party::load(&party_state);
 
     let n_signers = keygen_state.n_signers;
     let MpcParty { delivery, .. } = network.into_party();
     let (incomings, mut outgoings) = delivery.split();
-    let mut state = WstsSigningState::new(
-        signer.party_id,
-        n_signers,
-        threshold,
-        message.clone(),
-        keygen_state.public_key_frost_format.clone(),
-    );
+    let mut states = messages
+        .iter()
+        .map(|message| {
+            WstsSigningState::new(
+                signer.party_id,
+                n_signers,
+                threshold,
+                message.clone(),
+                keygen_state.public_key_frost_format.clone(),
+            )
+        })
+        .collect_vec();
+
+    // Weigh every party by the number of key ids it was given during keygen, rather than by
+    // whatever key ids it claims when signing
+    let key_weights = keygen_state
+        .key_ids
+        .iter()
+        .map(|(party_id, key_ids)| (*party_id as u16, key_ids.len()))
+        .collect();
 
     let mut rounds = RoundsRouter::builder();
-    let round1 = rounds.add_round(RoundInput::<Round1Msg>::broadcast(
-        state.party_id as _,
-        n_signers as _,
-    ));
-    let round2 = rounds.add_round(RoundInput::<Round2Msg>::broadcast(
-        state.party_id as _,
-        n_signers as _,
+    let round1 = rounds.add_round(ThresholdRoundInput::<Round1Msg>::new(
+        signer.party_id as _,
+        key_weights,
+        threshold as _,
     ));
+    let signer_set_round = rounds.add_round(CoordinatorRoundInput::new(coordinator as _));
+    let round2 = rounds.add_round(SignerSetRoundInput::new(signer.party_id, coordinator));
     let mut rounds = rounds.listen(incomings);
 
-    // Round 1: Generate and broadcast nonce
-    let nonce = signer.gen_nonce(rng);
-    let key_ids = signer.key_ids.clone();
+    // Round 1: Generate and broadcast a nonce for each message. This is done even if the
+    // coordinator goes on to pick preprocessed nonces, since we can not know that yet
+    let mut nonces = Vec::with_capacity(messages.len());
+    let mut fresh_secret_nonces = Vec::with_capacity(messages.len());
+    for _ in 0..messages.len() {
+        let nonce = Nonce::random(rng);
+        nonces.push(PublicNonce::from(&nonce));
+        fresh_secret_nonces.push(nonce);
+    }
+    ledger.advance(&nonces, NonceStatus::Generated)?;
 
     let my_round1 = Round1Msg {
         source: signer.party_id,
-        key_ids: key_ids.clone(),
-        nonce: nonce.clone(),
+        key_ids: signer.key_ids.clone(),
+        nonces: nonces.clone(),
+        refusal: None,
     };
 
     let msg = Msg::Round1(my_round1.clone());
     send_message::<M, _>(msg, &mut outgoings).await?;
+    ledger.advance(&nonces, NonceStatus::Committed)?;
 
-    let round1_msgs = rounds
-        .complete(round1)
-        .await
-        .map_err(|err| SigningError::MpcError(err.to_string()))?;
+    // The coordinator signs with preprocessed nonces if it has one for every message, without
+    // waiting for round 1. Otherwise it picks the signers from round 1
+    let mut preprocessed = Vec::new();
+    let signer_set = if signer.party_id == coordinator {
+        preprocessed = pool.take_next(messages.len());
+        let signer_set = if preprocessed.is_empty() {
+            let round1_msgs = rounds
+                .complete(round1)
+                .await
+                .map_err(|err| SigningError::MpcError(err.to_string()))?;
+            choose_signers(
+                keygen_state,
+                threshold,
+                signer.party_id,
+                &nonces,
+                round1_msgs,
+            )
+        } else {
+            SignerSetMsg {
+                source: signer.party_id,
+                nonces: BTreeMap::new(),
+                nonce_slots: preprocessed.iter().map(|nonce| nonce.slot).collect(),
+                refusals: Vec::new(),
+            }
+        };
 
-    let round1_msgs: HashMap<u32, Round1Msg> = round1_msgs
-        .into_iter_including_me(my_round1)
-        .map(|r| (r.source, r))
-        .collect();
+        let msg = Msg::SignerSet(signer_set.clone());
+        send_message::<M, _>(msg, &mut outgoings).await?;
+        signer_set
+    } else {
+        rounds
+            .complete(signer_set_round)
+            .await
+            .map_err(|err| SigningError::MpcError(err.to_string()))?
+    };
+
+    // The coordinator gives up on the session if too many parties refused it
+    if !signer_set.refusals.is_empty() {
+        return Err(SigningError::Refused {
+            refusals: signer_set.refusals,
+        });
+    }
+
+    // Every signer's public nonces, one per message, and our secret nonces if we are one
+    let mut party_nonces: BTreeMap<u32, Vec<PublicNonce>> = BTreeMap::new();
+    let mut secret_nonces = Vec::with_capacity(messages.len());
+    let nonce_slots = signer_set.nonce_slots;
+
+    if !nonce_slots.is_empty() {
+        // Every party takes exactly the nonces the coordinator named. Every party from
+        // keygen took part in preprocessing, so they all sign
+        if preprocessed.is_empty() {
+            preprocessed = pool.take_slots(&nonce_slots).ok_or_else(|| {
+                SigningError::ContextError(format!(
+                    "Coordinator {coordinator} named preprocessed nonces {nonce_slots:?} that we do not have"
+                ))
+            })?;
+        }
 
-    // Process round 1 messages
-    for (party_id, msg) in round1_msgs {
-        state.party_key_ids.insert(party_id, msg.key_ids);
-        state.party_nonces.insert(party_id, msg.nonce);
+        if preprocessed.len() != messages.len() {
+            return Err(SigningError::MpcError(format!(
+                "Coordinator {coordinator} named {} preprocessed nonces for {} messages",
+                preprocessed.len(),
+                messages.len()
+            )));
+        }
+
+        info!(
+            "Party {} signing with preprocessed nonces {nonce_slots:?}",
+            signer.party_id
+        );
+
+        for preprocessed in preprocessed {
+            secret_nonces.push(preprocessed.secret);
+            for (party_id, nonce) in preprocessed.public_nonces {
+                party_nonces.entry(party_id).or_default().push(nonce);
+            }
+        }
+    } else {
+        let weight = signer_set
+            .nonces
+            .keys()
+            .map(|party_id| keygen_state.key_ids.get(party_id).map_or(0, Vec::len))
+            .sum::<usize>();
+        if weight < threshold as usize || !signer_set.nonces.contains_key(&coordinator) {
+            return Err(SigningError::MpcError(format!(
+                "Coordinator {coordinator} picked an invalid signer set {:?}",
+                signer_set.nonces.keys().collect_vec()
+            )));
+        }
+
+        // If we are a signer, the coordinator must have passed on our nonces unchanged
+        if let Some(our_nonces) = signer_set.nonces.get(&signer.party_id) {
+            let unchanged = our_nonces.len() == nonces.len()
+                && our_nonces
+                    .iter()
+                    .zip(&nonces)
+                    .all(|(a, b)| same_nonce(a, b));
+            if !unchanged {
+                return Err(SigningError::MpcError(format!(
+                    "Coordinator {coordinator} changed our nonces"
+                )));
+            }
+        }
+
+        party_nonces = signer_set.nonces;
+        secret_nonces = fresh_secret_nonces;
+    }
+
+    if let Some((party_id, _)) = party_nonces
+        .iter()
+        .find(|(_, nonces)| nonces.len() != messages.len())
+    {
+        return Err(SigningError::MpcError(format!(
+            "Party {party_id} did not send one nonce per message"
+        )));
+    }
+
+    let mut signer_key_ids = HashMap::new();
+    for party_id in party_nonces.keys() {
+        let key_ids = keygen_state.key_ids.get(party_id).ok_or_else(|| {
+            SigningError::ContextError(format!("No key ids for party {party_id}"))
+        })?;
+        signer_key_ids.insert(*party_id, key_ids.clone());
     }
 
     // Sort and prepare for signing
-    let party_ids = state
-        .party_key_ids
-        .keys()
-        .copied()
-        .sorted_by(|a, b| a.cmp(b))
-        .collect_vec();
-    let party_key_ids = state
-        .party_key_ids
+    let party_ids = party_nonces.keys().copied().collect_vec();
+    let party_key_ids = signer_key_ids
         .clone()
         .into_iter()
         .sorted_by(|a, b| a.0.cmp(&b.0))
         .flat_map(|r| r.1)
         .collect_vec();
-    let party_nonces = state
-        .party_nonces
-        .clone()
-        .into_iter()
-        .sorted_by(|a, b| a.0.cmp(&b.0))
-        .map(|r| r.1)
-        .collect_vec();
+    let nonces_for = |index: usize| {
+        party_nonces
+            .values()
+            .map(|nonces| nonces[index].clone())
+            .collect_vec()
+    };
 
-    // Round 2: Generate and broadcast signature share
-    let signature_share = signer.sign(&message, &party_ids, &party_key_ids, &party_nonces);
+    let is_signer = party_nonces.contains_key(&signer.party_id);
+    info!(
+        "Party {} {} {} message(s) with parties {party_ids:?}",
+        signer.party_id,
+        if is_signer { "signing" } else { "aggregating" },
+        messages.len()
+    );
 
-    let my_round2 = Round2Msg {
-        source: signer.party_id,
-        signature_share: signature_share.clone(),
-    };
+    // Every party negates its nonce and key shares as needed to sign for the format's key
+    let signature_key = format.signature_key(&party_state.group_key);
 
-    let msg = Msg::Round2(my_round2.clone());
-    send_message::<M, _>(msg, &mut outgoings).await?;
+    // Round 2: Generate and broadcast a signature share for each message. The nonces are
+    // marked as consumed before any share exists, so a crash afterwards can never lead to a
+    // second share with them. Preprocessed nonces left the pool before they were handed to us,
+    // which already keeps them from being used twice. Parties the coordinator left out of the
+    // signer set never use their nonces, and only aggregate the signers' shares
+    let mut my_round2 = None;
+    if is_signer {
+        if nonce_slots.is_empty() {
+            ledger.advance(&party_nonces[&signer.party_id], NonceStatus::Consumed)?;
+        }
 
-    let round2_msgs = rounds
-        .complete(round2)
-        .await
-        .map_err(|err| SigningError::MpcError(err.to_string()))?;
+        let mut signature_shares = Vec::with_capacity(messages.len());
+        for (index, (message, secret_nonce)) in messages.iter().zip(&secret_nonces).enumerate() {
+            signature_shares.push(sign_share(
+                &party_state,
+                secret_nonce,
+                message,
+                &party_ids,
+                &party_key_ids,
+                &nonces_for(index),
+                &signature_key,
+            ));
+        }
 
-    let round2_msgs: HashMap<u32, Round2Msg> = round2_msgs
-        .into_iter_including_me(my_round2)
-        .map(|r| (r.source, r))
-        .collect();
+        let round2 = Round2Msg {
+            source: signer.party_id,
+            signers: party_ids.clone(),
+            nonce_slots: nonce_slots.clone(),
+            signature_shares,
+            refusal: None,
+        };
 
-    // Process round 2 messages
-    for (party_id, msg) in round2_msgs {
-        state.signature_shares.insert(party_id, msg.signature_share);
+        let msg = Msg::Round2(round2.clone());
+        send_message::<M, _>(msg, &mut outgoings).await?;
+        my_round2 = Some(round2);
     }
 
-    // Sort signature shares and aggregate
-    let signature_shares = state
-        .signature_shares
-        .clone()
-        .into_iter()
-        .sorted_by(|a, b| a.0.cmp(&b.0))
-        .map(|r| r.1)
-        .collect_vec();
+    // If we own enough key ids to sign alone, there are no other shares to wait for
+    let mut round2_msgs = BTreeMap::new();
+    if party_ids
+        .iter()
+        .any(|party_id| *party_id != signer.party_id)
+    {
+        let (signers, msgs) = match rounds
+            .complete(round2)
+            .await
+            .map_err(|err| SigningError::MpcError(err.to_string()))?
+        {
+            SignerSetOutput::Shares(signers, msgs) => (signers, msgs),
+            SignerSetOutput::Refused(refusals) => {
+                return Err(SigningError::Refused { refusals });
+            }
+        };
+
+        if signers != party_ids {
+            return Err(SigningError::MpcError(format!(
+                "Signer set mismatch: we signed for {party_ids:?}, others for {signers:?}"
+            )));
+        }
+
+        if let Some((party_id, msg)) = msgs.iter().find(|(_, msg)| msg.nonce_slots != nonce_slots) {
+            return Err(SigningError::MpcError(format!(
+                "Nonce mismatch: we signed with {nonce_slots:?}, party {party_id} with {:?}",
+                msg.nonce_slots
+            )));
+        }
+
+        if let Some((party_id, _)) = msgs
+            .iter()
+            .find(|(_, msg)| msg.signature_shares.len() != messages.len())
+        {
+            return Err(SigningError::MpcError(format!(
+                "Party {party_id} did not send one signature share per message"
+            )));
+        }
+
+        round2_msgs = msgs;
+    }
+    if let Some(my_round2) = my_round2 {
+        round2_msgs.insert(signer.party_id, my_round2);
+    }
 
     let public_key_comm = keygen_state
         .poly_commitments
@@ -230,59 +741,311 @@ where
         .map(|r| r.1.clone())
         .collect_vec();
 
-    // Create signature aggregator
-    let mut sig_agg =
-        SignatureAggregator::new(state.n_signers as u32, state.threshold, public_key_comm)
-            .map_err(|err| SigningError::MpcError(err.to_string()))?;
+    // Create signature aggregator. Key ids are allocated by weight, so the total number of
+    // keys is not necessarily the number of signers
+    let num_keys = keygen_state.key_ids.values().map(Vec::len).sum::<usize>() as u32;
+    let sig_agg = SignatureAggregator::new(num_keys, threshold, public_key_comm)
+        .map_err(|err| SigningError::MpcError(err.to_string()))?;
+
+    // Signatures verify against the format's key, which for Taproot is the output key rather
+    // than the group key
+    let frost_verifying_key = VerifyingKey::deserialize(&signature_key.public_key.compress().data)
+        .map_err(|_| SigningError::InvalidFrostVerifyingKey)?;
+
+    for (index, state) in states.iter_mut().enumerate() {
+        let message = &messages[index];
+        let party_nonces = nonces_for(index);
+
+        // Process round 2 messages. A share must be for its sender and the key ids the
+        // sender was given during keygen, or it could be passed off as someone else's
+        let mut culprits = Vec::new();
+        for (party_id, msg) in &round2_msgs {
+            let share = &msg.signature_shares[index];
+            if share.id != *party_id || Some(&share.key_ids) != signer_key_ids.get(party_id) {
+                culprits.push(*party_id);
+            }
+
+            state.signature_shares.insert(*party_id, share.clone());
+        }
+
+        if !culprits.is_empty() {
+            return Err(SigningError::Blame { culprits });
+        }
+        state.party_key_ids = signer_key_ids.clone();
+        state.party_nonces = party_ids
+            .iter()
+            .copied()
+            .zip(party_nonces.iter().cloned())
+            .collect();
+
+        // Sort signature shares and aggregate
+        let signature_shares = state
+            .signature_shares
+            .clone()
+            .into_iter()
+            .sorted_by(|a, b| a.0.cmp(&b.0))
+            .map(|r| r.1)
+            .collect_vec();
 
-    // Generate final signature
-    let wsts_sig = sig_agg
-        .sign(&message, &party_nonces, &signature_shares, &party_key_ids)
+        // Generate final signature
+        let wsts_sig = aggregate_signature_shares(
+            &sig_agg.poly,
+            message,
+            &party_nonces,
+            &signature_shares,
+            &party_key_ids,
+            &signature_key,
+        )?;
+
+        // Verify WSTS signature
+        if !wsts_sig.verify(&signature_key.public_key, message) {
+            return Err(SigningError::InvalidSignature);
+        }
+
+        // Convert to FROST format. BIP340 signatures have an even `R`, so their x-only
+        // encoding only drops the parity prefix
+        let mut signature_bytes = [0u8; 33 + 32];
+        let r = wsts_sig.R.compress();
+        signature_bytes[0..33].copy_from_slice(&r.data);
+        signature_bytes[33..].copy_from_slice(&wsts_sig.z.to_bytes());
+
+        state.signature_frost_format = signature_bytes.to_vec();
+
+        // Verify FROST signature
+        let frost_signature = frost_secp256k1_tr::Signature::deserialize(&signature_bytes)
+            .map_err(|_| SigningError::InvalidFrostSignature)?;
+
+        frost_verifying_key
+            .verify(message, &frost_signature)
+            .map_err(|_| SigningError::InvalidFrostVerification)?;
+
+        Secp256K1Sha256TR::verify_signature(message, &frost_signature, &frost_verifying_key)
+            .map_err(|_| SigningError::InvalidFrostVerification)?;
+
+        state.party = Arc::new(parking_lot::Mutex::new(Some(signer.save())));
+        state.aggregated_signature = Some(Arc::new(wsts_sig.into()));
+    }
+
+    Ok(states)
+}
+
+/// Picks the signers for a session from the round 1 messages the coordinator collected,
+/// which is everyone that sent nonces before the threshold was reached. If too many parties
+/// refused for it to be reached, the set is left empty and carries their refusals instead
+fn choose_signers(
+    keygen_state: &WstsState,
+    threshold: u32,
+    party_id: u32,
+    nonces: &[PublicNonce],
+    round1_msgs: BTreeMap<u16, Round1Msg>,
+) -> SignerSetMsg {
+    let (refusals, round1_msgs): (Vec<_>, Vec<_>) = round1_msgs
+        .into_iter()
+        .partition(|(_, msg)| msg.refusal.is_some());
+
+    let weight = std::iter::once(party_id)
+        .chain(round1_msgs.iter().map(|(party_id, _)| *party_id as u32))
+        .map(|party_id| keygen_state.key_ids.get(&party_id).map_or(0, Vec::len))
+        .sum::<usize>();
+
+    if weight < threshold as usize {
+        return SignerSetMsg {
+            source: party_id,
+            nonces: BTreeMap::new(),
+            nonce_slots: Vec::new(),
+            refusals: refusals
+                .into_iter()
+                .filter_map(|(_, msg)| msg.refusal)
+                .collect(),
+        };
+    }
+
+    let nonces = std::iter::once((party_id, nonces.to_vec()))
+        .chain(
+            round1_msgs
+                .into_iter()
+                .map(|(party_id, msg)| (party_id as u32, msg.nonces)),
+        )
+        .collect();
+
+    SignerSetMsg {
+        source: party_id,
+        nonces,
+        nonce_slots: Vec::new(),
+        refusals: Vec::new(),
+    }
+}
+
+/// Tells the other parties of a signing session that our signing policy rejected it, so they
+/// can abort instead of waiting for us. The refusal is sent in round 1, and repeated in round 2
+/// if the coordinator picks preprocessed nonces, since the signers then skip round 1. The named
+/// nonces are taken all the same, to keep our pool in step with everyone else's. A refusing
+/// coordinator can not pick signers, so it ends the session for everyone
+pub async fn wsts_refusal_protocol<M>(
+    network: M,
+    keygen_state: &WstsState,
+    refusal: SigningRefusal,
+    coordinator: u32,
+    pool: &impl PreprocessedNonces,
+) -> Result<(), SigningError>
+where
+    M: Mpc<ProtocolMessage = Msg>,
+{
+    let MpcParty { delivery, .. } = network.into_party();
+    let (incomings, mut outgoings) = delivery.split();
+
+    let source = refusal.party_id;
+    if source == coordinator {
+        let msg = Msg::SignerSet(SignerSetMsg {
+            source,
+            nonces: BTreeMap::new(),
+            nonce_slots: Vec::new(),
+            refusals: vec![refusal],
+        });
+        return send_message::<M, _>(msg, &mut outgoings).await;
+    }
+
+    let mut rounds = RoundsRouter::builder();
+    let _round1 = rounds.add_round(RoundInput::<Round1Msg>::broadcast(
+        source as _,
+        keygen_state.n_signers as _,
+    ));
+    let signer_set_round = rounds.add_round(CoordinatorRoundInput::new(coordinator as _));
+    let _round2 = rounds.add_round(SignerSetRoundInput::new(source, coordinator));
+    let mut rounds = rounds.listen(incomings);
+
+    let msg = Msg::Round1(Round1Msg {
+        source,
+        key_ids: keygen_state
+            .key_ids
+            .get(&source)
+            .cloned()
+            .unwrap_or_default(),
+        nonces: Vec::new(),
+        refusal: Some(refusal.clone()),
+    });
+    send_message::<M, _>(msg, &mut outgoings).await?;
+
+    let signer_set = rounds
+        .complete(signer_set_round)
+        .await
         .map_err(|err| SigningError::MpcError(err.to_string()))?;
+    if signer_set.nonce_slots.is_empty() {
+        return Ok(());
+    }
 
-    // Verify WSTS signature
-    let compressed_public_key =
-        p256k1::point::Compressed::try_from(state.public_key_frost_format.as_slice())
-            .map_err(|_| SigningError::InvalidPublicKey)?;
+    if pool.take_slots(&signer_set.nonce_slots).is_none() {
+        warn!(
+            "Coordinator {coordinator} named preprocessed nonces {:?} that we do not have",
+            signer_set.nonce_slots
+        );
+    }
 
-    let wsts_public_key = p256k1::point::Point::try_from(&compressed_public_key)
-        .map_err(|_| SigningError::InvalidPublicKey)?;
+    let msg = Msg::Round2(Round2Msg {
+        source,
+        signers: Vec::new(),
+        nonce_slots: Vec::new(),
+        signature_shares: Vec::new(),
+        refusal: Some(refusal),
+    });
+    send_message::<M, _>(msg, &mut outgoings).await
+}
 
-    if !wsts_sig.verify(&wsts_public_key, &message) {
-        return Err(SigningError::InvalidSignature);
+/// Returns `-1` if `negate`, and `1` otherwise
+fn sign_of(negate: bool) -> Scalar {
+    if negate {
+        -Scalar::from(1)
+    } else {
+        Scalar::from(1)
     }
+}
+
+/// Our share of the signature over `message`, signed with the secret `nonce`. This is
+/// `Party::sign_with_tweak`, except that the nonce is passed in, since a saved party does not
+/// keep one, and that the key shares and nonce are negated as `key` asks rather than by the
+/// tweak being nonzero
+#[allow(non_snake_case)]
+fn sign_share(
+    party: &PartyState,
+    nonce: &Nonce,
+    message: &[u8],
+    party_ids: &[u32],
+    key_ids: &[u32],
+    nonces: &[PublicNonce],
+    key: &SignatureKey,
+) -> SignatureShare {
+    let (_, R) = compute::intermediate(message, party_ids, nonces);
+    let c = compute::challenge(&key.public_key, &R, message);
+
+    let r = nonce.d + nonce.e * compute::binding(&compute::id(party.party_id), nonces, message);
+    let cx = party.key_ids.iter().fold(Scalar::from(0), |cx, key_id| {
+        cx + c * party.private_keys[key_id] * compute::lambda(*key_id, key_ids)
+    });
 
-    // Convert to FROST format and verify
-    let mut signature_bytes = [0u8; 33 + 32];
-    let r = wsts_sig.R.compress();
-    signature_bytes[0..33].copy_from_slice(&r.data);
-    signature_bytes[33..].copy_from_slice(&wsts_sig.z.to_bytes());
+    SignatureShare {
+        id: party.party_id,
+        z_i: sign_of(key.even_nonce && !R.has_even_y()) * r + key.key_sign * cx,
+        key_ids: party.key_ids.clone(),
+    }
+}
 
-    state.signature_frost_format = signature_bytes.to_vec();
+/// Checks every signature share against the signer's public key shares, which are read off
+/// the group polynomial, and sums them into a signature for `key`. Any signer whose share
+/// does not verify is blamed
+#[allow(non_snake_case)]
+fn aggregate_signature_shares(
+    group_poly: &[Point],
+    message: &[u8],
+    nonces: &[PublicNonce],
+    signature_shares: &[SignatureShare],
+    key_ids: &[u32],
+    key: &SignatureKey,
+) -> Result<Signature, SigningError> {
+    let party_ids = signature_shares.iter().map(|share| share.id).collect_vec();
+    let (R_vec, R) = compute::intermediate(message, &party_ids, nonces);
+    let c = compute::challenge(&key.public_key, &R, message);
+    let r_sign = sign_of(key.even_nonce && !R.has_even_y());
+    let group_poly = group_poly.to_vec();
 
-    let frost_signature = frost_secp256k1_tr::Signature::deserialize(&signature_bytes)
-        .map_err(|_| SigningError::InvalidFrostSignature)?;
+    let mut culprits = Vec::new();
+    let mut z = Scalar::from(0);
+    for (share, R_i) in signature_shares.iter().zip(R_vec) {
+        let public_keys = share
+            .key_ids
+            .iter()
+            .map(|key_id| {
+                let public_key = compute::poly(&compute::id(*key_id), &group_poly).ok()?;
+                Some(compute::lambda(*key_id, key_ids) * public_key)
+            })
+            .collect::<Option<Vec<_>>>();
 
-    let frost_verifying_key = VerifyingKey::deserialize(&state.public_key_frost_format)
-        .map_err(|_| SigningError::InvalidFrostVerifyingKey)?;
+        let valid = public_keys.is_some_and(|public_keys| {
+            let cx = public_keys
+                .into_iter()
+                .fold(Point::new(), |acc, public_key| acc + public_key);
+            share.z_i * G == r_sign * R_i + key.key_sign * c * cx
+        });
+        if !valid {
+            culprits.push(share.id);
+        }
 
-    frost_verifying_key
-        .verify(&message, &frost_signature)
-        .map_err(|_| SigningError::InvalidFrostVerification)?;
+        z += share.z_i;
+    }
 
-    Secp256K1Sha256TR::verify_signature(&message, &frost_signature, &frost_verifying_key)
-        .map_err(|_| SigningError::InvalidFrostVerification)?;
+    if !culprits.is_empty() {
+        return Err(SigningError::Blame { culprits });
+    }
 
-    state.party = Arc::new(parking_lot::Mutex::new(Some(signer.save())));
-    state.aggregated_signature = Some(Arc::new(wsts_sig.into()));
+    // The tweak's part of the key is not held by any signer, so it is added once here
+    z += c * key.tweak;
 
-    Ok(state)
+    Ok(Signature { R: r_sign * R, z })
 }
 
 impl HasRecipient for Msg {
     fn recipient(&self) -> MessageDestination {
         match self {
-            Msg::Round1(_) | Msg::Round2(_) => MessageDestination::AllParties,
+            Msg::Round1(_) | Msg::SignerSet(_) | Msg::Round2(_) => MessageDestination::AllParties,
         }
     }
 }
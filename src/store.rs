@@ -0,0 +1,851 @@
+use crate::keygen_state_machine::WstsState;
+use crate::preprocessing_state_machine::NonceRecord;
+use crate::schema::{self, SchemaError, CURRENT_SCHEMA_VERSION};
+use blueprint_sdk::crypto::KeyEncoding;
+use blueprint_sdk::logging::{info, warn};
+use blueprint_sdk::networking::GossipMsgKeyPair;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use color_eyre::eyre;
+use rand::RngCore;
+use redb::{ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The environment variable holding the passphrase to encrypt the key store with. If unset,
+/// the store is encrypted with a key derived from the operator's identity key
+pub const STORE_PASSPHRASE_ENV: &str = "WSTS_STORE_PASSPHRASE";
+
+/// The environment variable selecting the [`StoreBackend`], `json` or `redb`. Defaults to
+/// `json`
+pub const STORE_BACKEND_ENV: &str = "WSTS_STORE_BACKEND";
+
+/// The key store file of the [`JsonKeyShareStore`] backend, in the keystore directory
+pub const JSON_STORE_FILE: &str = "wsts.json";
+
+/// The database file of the [`RedbKeyShareStore`] backend, in the keystore directory
+pub const REDB_STORE_FILE: &str = "wsts.redb";
+
+/// What the [`JSON_STORE_FILE`] is renamed to once its records are copied to the
+/// [`REDB_STORE_FILE`], so that they are not copied again
+pub const MIGRATED_JSON_STORE_FILE: &str = "wsts.json.migrated";
+
+/// Domain separator for the store encryption key
+const STORE_KEY_SALT: &str = "wsts-store-key";
+
+/// The salt passed to [`compute_execution_hashes`](crate::compute_execution_hashes) to recompute
+/// legacy store keys. It only affects the second hash, so any salt gives the same `meta_hash`
+const LEGACY_STORE_KEY_SALT: &str = "wsts-legacy-store-key";
+
+/// How many keygen call ids are tried when looking for the keygen job of a legacy store key
+const LEGACY_CALL_ID_SEARCH_LIMIT: u64 = 1 << 22;
+
+/// The prefix of the store keys of nonce ledgers, which are kept next to the key shares so
+/// that each signing session writes its own record rather than the key's
+const NONCE_LEDGER_PREFIX: &str = "nonce-ledger/";
+
+/// The prefix of the markers recording that the legacy keys of a service were migrated, so
+/// that the search for their keygen jobs runs once rather than on every start
+const LEGACY_MIGRATION_PREFIX: &str = "migrated-legacy-keys/";
+
+/// The table of the [`RedbKeyShareStore`] that holds every record, by store key
+const KEY_SHARES_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("key_shares");
+
+/// Error type for key store operations
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to serialize data: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Store backend error: {0}")]
+    BackendError(String),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error(transparent)]
+    SchemaError(#[from] SchemaError),
+}
+
+impl From<redb::Error> for StoreError {
+    fn from(err: redb::Error) -> Self {
+        StoreError::BackendError(err.to_string())
+    }
+}
+
+/// Identifies a stored key by the service instance and keygen job that generated it. This is
+/// independent of the operator set, so a key can still be found after operators join or leave
+/// the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyId {
+    pub service_id: u64,
+    pub keygen_call_id: u64,
+    /// Starts at [`KeyId::INITIAL_VERSION`], and is reserved for replacing the shares of a key
+    /// without changing its id. Nothing replaces shares yet, so every key is at that version
+    pub version: u32,
+}
+
+impl KeyId {
+    /// The version of a key as it was generated by keygen
+    pub const INITIAL_VERSION: u32 = 0;
+
+    pub fn new(service_id: u64, keygen_call_id: u64) -> Self {
+        Self {
+            service_id,
+            keygen_call_id,
+            version: Self::INITIAL_VERSION,
+        }
+    }
+
+    /// The key the shares are stored under, `{service_id}/{keygen_call_id}/{version}`
+    pub fn store_key(&self) -> String {
+        self.to_string()
+    }
+
+    /// Whether `store_key` is a `hex(meta_hash)` key from before keys were identified by
+    /// [`KeyId`]
+    pub fn is_legacy_store_key(store_key: &str) -> bool {
+        store_key.len() == 64 && store_key.bytes().all(|byte| byte.is_ascii_hexdigit())
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.service_id, self.keygen_call_id, self.version
+        )
+    }
+}
+
+impl FromStr for KeyId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split('/').collect::<Vec<_>>();
+        let [service_id, keygen_call_id, version] = parts.as_slice() else {
+            return Err(format!("Invalid key id {s}"));
+        };
+
+        let parse_err = |err: std::num::ParseIntError| format!("Invalid key id {s}: {err}");
+        Ok(Self {
+            service_id: service_id.parse().map_err(parse_err)?,
+            keygen_call_id: keygen_call_id.parse().map_err(parse_err)?,
+            version: version.parse().map_err(parse_err)?,
+        })
+    }
+}
+
+/// The store key of the nonce ledger of a signing session with the key under `store_key`
+pub fn nonce_ledger_key(store_key: &str, session_id: &str) -> String {
+    format!("{NONCE_LEDGER_PREFIX}{store_key}/{session_id}")
+}
+
+/// A storage backend for encrypted key shares. Every operation is atomic, so a backend can be
+/// shared by concurrent jobs
+pub trait KeyShareStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<EncryptedRecord>, StoreError>;
+
+    fn put(&self, key: &str, record: EncryptedRecord) -> Result<(), StoreError>;
+
+    fn delete(&self, key: &str) -> Result<(), StoreError>;
+
+    /// Returns the key of every stored record, in no particular order
+    fn list(&self) -> Result<Vec<String>, StoreError>;
+
+    /// Replaces the record under `key` with `new`, but only if it is still `current`. A `None`
+    /// stands for no record, so this can also insert and delete. Returns whether the record
+    /// was replaced
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: Option<&EncryptedRecord>,
+        new: Option<EncryptedRecord>,
+    ) -> Result<bool, StoreError>;
+}
+
+/// The available [`KeyShareStore`] backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreBackend {
+    /// Every record in one JSON file, rewritten on every update. Fine for a handful of keys
+    Json,
+    /// An embedded transactional key-value database, which only writes the records that
+    /// change
+    Redb,
+}
+
+impl StoreBackend {
+    /// Reads the backend from [`STORE_BACKEND_ENV`]
+    pub fn from_env() -> eyre::Result<Self> {
+        match std::env::var(STORE_BACKEND_ENV).ok().as_deref() {
+            None | Some("json") => Ok(StoreBackend::Json),
+            Some("redb") => Ok(StoreBackend::Redb),
+            Some(other) => Err(eyre::eyre!(
+                "Unknown {STORE_BACKEND_ENV} {other}, expected json or redb"
+            )),
+        }
+    }
+}
+
+/// A [`WstsState`] encrypted with XChaCha20-Poly1305, bound to the store key it is kept under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedRecord {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// An entry of the key store file as it may be found on disk, from before or after the store
+/// was encrypted. Plaintext entries are kept as they are, to be migrated by [`schema`]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredRecord {
+    Encrypted(EncryptedRecord),
+    Plaintext(serde_json::Value),
+}
+
+/// Encrypts and decrypts the records of the key store
+#[derive(Clone)]
+pub struct StoreCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl StoreCipher {
+    /// Derives the store key from `passphrase` with Argon2id if one is given, and from the
+    /// operator's identity key otherwise
+    pub fn new(identity: &GossipMsgKeyPair, passphrase: Option<&str>) -> eyre::Result<Self> {
+        let mut key = [0u8; 32];
+        match passphrase {
+            Some(passphrase) => {
+                // The salt only needs to be unique to the operator, not secret
+                let salt =
+                    crate::compute_sha256_hash!(STORE_KEY_SALT, identity.public().to_bytes());
+                argon2::Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                    .map_err(|err| eyre::eyre!("Failed to derive the store key: {err}"))?;
+            }
+            None => {
+                key = crate::compute_sha256_hash!(STORE_KEY_SALT, identity.to_bytes());
+            }
+        }
+
+        Ok(Self {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Builds the cipher from [`STORE_PASSPHRASE_ENV`], or from the identity key if it is unset
+    pub fn from_env(identity: &GossipMsgKeyPair) -> eyre::Result<Self> {
+        let passphrase = std::env::var(STORE_PASSPHRASE_ENV).ok();
+        Self::new(identity, passphrase.as_deref())
+    }
+
+    /// Encrypts `state` in the current schema version
+    pub fn encrypt(
+        &self,
+        store_key: &str,
+        state: &WstsState,
+    ) -> Result<EncryptedRecord, StoreError> {
+        self.seal(store_key, &schema::encode_state(state)?)
+    }
+
+    /// Decrypts a state, migrating it to the current schema version
+    pub fn decrypt(
+        &self,
+        store_key: &str,
+        record: &EncryptedRecord,
+    ) -> Result<WstsState, StoreError> {
+        let (state, _) = schema::decode_state(&self.open(store_key, record)?)?;
+        Ok(state)
+    }
+
+    fn seal(&self, store_key: &str, plaintext: &[u8]) -> Result<EncryptedRecord, StoreError> {
+        let mut nonce = [0u8; 24];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+        let payload = Payload {
+            msg: plaintext,
+            aad: store_key.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| {
+                StoreError::EncryptionError(format!("Failed to encrypt key {store_key}"))
+            })?;
+
+        Ok(EncryptedRecord {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    fn open(&self, store_key: &str, record: &EncryptedRecord) -> Result<Vec<u8>, StoreError> {
+        if record.nonce.len() != 24 {
+            return Err(StoreError::EncryptionError(format!(
+                "Invalid nonce for key {store_key}"
+            )));
+        }
+
+        let payload = Payload {
+            msg: &record.ciphertext,
+            aad: store_key.as_bytes(),
+        };
+        self.cipher
+            .decrypt(XNonce::from_slice(&record.nonce), payload)
+            .map_err(|_| {
+                StoreError::EncryptionError(format!(
+                    "Failed to decrypt key {store_key}, wrong store key?"
+                ))
+            })
+    }
+}
+
+/// The key store, which keeps every [`WstsState`] encrypted in a [`KeyShareStore`] backend
+pub struct EncryptedStore<S: KeyShareStore + ?Sized = dyn KeyShareStore> {
+    cipher: StoreCipher,
+    backend: Arc<S>,
+}
+
+impl EncryptedStore {
+    /// Opens the store in `keystore_dir` with the given backend, first encrypting any records
+    /// left in plaintext by older versions. When switching to the [`StoreBackend::Redb`]
+    /// backend, the records of an existing [`JSON_STORE_FILE`] are copied over and the file is
+    /// renamed to [`MIGRATED_JSON_STORE_FILE`]. Once a [`REDB_STORE_FILE`] exists, the
+    /// [`StoreBackend::Json`] backend is refused, since it would serve stale keys
+    ///
+    /// Every record is then migrated to the current schema version, so that a layout this
+    /// build cannot read is reported at startup rather than when the key is next used
+    pub fn open(
+        keystore_dir: &Path,
+        backend: StoreBackend,
+        cipher: StoreCipher,
+    ) -> eyre::Result<Self> {
+        let json_path = keystore_dir.join(JSON_STORE_FILE);
+        let migrated = migrate_plaintext(&json_path, &cipher)?;
+        if migrated > 0 {
+            info!(
+                "Encrypted {migrated} plaintext key(s) in {}",
+                json_path.display()
+            );
+        }
+
+        let backend: Arc<dyn KeyShareStore> = match backend {
+            StoreBackend::Json => {
+                let redb_path = keystore_dir.join(REDB_STORE_FILE);
+                if redb_path.exists() {
+                    return Err(eyre::eyre!(
+                        "The keys were moved to {}, set {STORE_BACKEND_ENV}=redb",
+                        redb_path.display()
+                    ));
+                }
+                Arc::new(JsonKeyShareStore::open(json_path)?)
+            }
+            StoreBackend::Redb => {
+                let db = RedbKeyShareStore::open(&keystore_dir.join(REDB_STORE_FILE))?;
+                if json_path.exists() {
+                    // Copying is idempotent, so a crash before the rename only repeats it
+                    let copied = copy_missing(&JsonKeyShareStore::open(json_path.clone())?, &db)?;
+                    std::fs::rename(&json_path, keystore_dir.join(MIGRATED_JSON_STORE_FILE))?;
+                    info!(
+                        "Copied {copied} key(s) from {JSON_STORE_FILE} to {REDB_STORE_FILE}, \
+                         moved {JSON_STORE_FILE} to {MIGRATED_JSON_STORE_FILE}"
+                    );
+                }
+                Arc::new(db)
+            }
+        };
+
+        let store = Self { cipher, backend };
+        let migrated = store.migrate_schema()?;
+        if migrated > 0 {
+            info!("Migrated {migrated} key(s) to schema version {CURRENT_SCHEMA_VERSION}");
+        }
+
+        Ok(store)
+    }
+}
+
+impl<S: KeyShareStore + ?Sized> EncryptedStore<S> {
+    pub fn new(backend: Arc<S>, cipher: StoreCipher) -> Self {
+        Self { cipher, backend }
+    }
+
+    pub fn get(&self, store_key: &str) -> Result<Option<WstsState>, StoreError> {
+        self.backend
+            .get(store_key)?
+            .map(|record| self.cipher.decrypt(store_key, &record))
+            .transpose()
+    }
+
+    pub fn set(&self, store_key: &str, state: &WstsState) -> Result<(), StoreError> {
+        let record = self.cipher.encrypt(store_key, state)?;
+        self.backend.put(store_key, record)
+    }
+
+    pub fn delete(&self, store_key: &str) -> Result<(), StoreError> {
+        self.backend.delete(store_key)
+    }
+
+    /// Returns the store key of every key share, leaving out nonce ledgers and migration
+    /// markers
+    pub fn keys(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self
+            .backend
+            .list()?
+            .into_iter()
+            .filter(|key| {
+                !key.starts_with(NONCE_LEDGER_PREFIX) && !key.starts_with(LEGACY_MIGRATION_PREFIX)
+            })
+            .collect())
+    }
+
+    /// Rewrites every record stored in an older schema version in the current one, returning
+    /// how many there were
+    pub fn migrate_schema(&self) -> Result<usize, StoreError> {
+        let mut migrated = 0;
+        for store_key in self.keys()? {
+            let Some(current) = self.backend.get(&store_key)? else {
+                continue;
+            };
+
+            let (state, version) = schema::decode_state(&self.cipher.open(&store_key, &current)?)?;
+            if version == CURRENT_SCHEMA_VERSION {
+                continue;
+            }
+
+            // If the record changed in the meantime, it was written in the current version
+            let record = self.cipher.encrypt(&store_key, &state)?;
+            if self
+                .backend
+                .compare_and_swap(&store_key, Some(&current), Some(record))?
+            {
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Moves every key stored by older versions under `hex(meta_hash)` to its [`KeyId`] in
+    /// the given service, returning how many there were. The legacy store key commits to the
+    /// keygen call id, which is recovered by trying every call id up to
+    /// [`LEGACY_CALL_ID_SEARCH_LIMIT`], and to the number of operators at keygen, which is
+    /// read from the key itself. Keys whose keygen job is not found are left in place
+    ///
+    /// The search only runs once per service. Once it has, a marker is stored, and later calls
+    /// return without looking at the legacy keys again
+    pub fn migrate_legacy_keys(
+        &self,
+        service_id: u64,
+        blueprint_id: u64,
+    ) -> Result<usize, StoreError> {
+        let marker = format!("{LEGACY_MIGRATION_PREFIX}{service_id}");
+        if self.backend.get(&marker)?.is_some() {
+            return Ok(0);
+        }
+
+        let mut legacy = HashMap::new();
+        for store_key in self.keys()? {
+            if !KeyId::is_legacy_store_key(&store_key) {
+                continue;
+            }
+
+            let mut meta_hash = [0u8; 32];
+            if hex::decode_to_slice(&store_key, &mut meta_hash).is_err() {
+                continue;
+            }
+            if let Some(state) = self.get(&store_key)? {
+                legacy.insert(meta_hash, (store_key, state.n_signers as u16));
+            }
+        }
+
+        // There is nothing to mark if there were never any legacy keys
+        let searched = !legacy.is_empty();
+        let signer_counts = legacy
+            .values()
+            .map(|(_, n)| *n)
+            .collect::<std::collections::BTreeSet<_>>();
+
+        let mut migrated = 0;
+        for keygen_call_id in 0..LEGACY_CALL_ID_SEARCH_LIMIT {
+            if legacy.is_empty() {
+                break;
+            }
+
+            for &n in &signer_counts {
+                let (meta_hash, _) = crate::compute_execution_hashes(
+                    n,
+                    blueprint_id,
+                    keygen_call_id,
+                    LEGACY_STORE_KEY_SALT,
+                );
+                let Some((store_key, _)) = legacy.remove(&meta_hash) else {
+                    continue;
+                };
+
+                let key_id = KeyId::new(service_id, keygen_call_id);
+                if self.rename(&store_key, &key_id.store_key())? {
+                    migrated += 1;
+                } else {
+                    warn!("Key {key_id} is already in the store, leaving legacy key {store_key}");
+                }
+            }
+        }
+
+        for (store_key, _) in legacy.values() {
+            warn!("No keygen job found for legacy key {store_key}, leaving it in place");
+        }
+
+        if searched {
+            self.backend.put(&marker, self.cipher.seal(&marker, &[])?)?;
+        }
+
+        Ok(migrated)
+    }
+
+    /// Moves the state stored under `from` to `to`, re-encrypting it for its new key. Returns
+    /// `false`, leaving both entries in place, if there is nothing under `from` or `to` is
+    /// already taken
+    pub fn rename(&self, from: &str, to: &str) -> Result<bool, StoreError> {
+        let Some(state) = self.get(from)? else {
+            return Ok(false);
+        };
+
+        let record = self.cipher.encrypt(to, &state)?;
+        if !self.backend.compare_and_swap(to, None, Some(record))? {
+            return Ok(false);
+        }
+
+        self.backend.delete(from)?;
+        Ok(true)
+    }
+
+    /// Applies `update` to the stored state of a key, returning `None` if there is no such key
+    ///
+    /// The state is written back with a compare-and-swap, and `update` is applied again to the
+    /// latest state if another writer got there first
+    pub fn update<T>(
+        &self,
+        store_key: &str,
+        mut update: impl FnMut(&mut WstsState) -> T,
+    ) -> Result<Option<T>, StoreError> {
+        loop {
+            let Some(current) = self.backend.get(store_key)? else {
+                return Ok(None);
+            };
+
+            let mut state = self.cipher.decrypt(store_key, &current)?;
+            let output = update(&mut state);
+            let record = self.cipher.encrypt(store_key, &state)?;
+
+            if self
+                .backend
+                .compare_and_swap(store_key, Some(&current), Some(record))?
+            {
+                return Ok(Some(output));
+            }
+        }
+    }
+}
+
+impl<S: KeyShareStore + ?Sized> EncryptedStore<S> {
+    /// Applies `update` to the nonce ledger under `ledger_key`, which starts out empty, and
+    /// writes it back unless `update` fails. Like [`Self::update`], `update` is applied again
+    /// to the latest ledger if another writer got there first
+    pub fn update_nonce_ledger<E>(
+        &self,
+        ledger_key: &str,
+        mut update: impl FnMut(&mut Vec<NonceRecord>) -> Result<(), E>,
+    ) -> Result<Result<(), E>, StoreError> {
+        loop {
+            let current = self.backend.get(ledger_key)?;
+            let mut records: Vec<NonceRecord> = match &current {
+                Some(record) => serde_json::from_slice(&self.cipher.open(ledger_key, record)?)?,
+                None => Vec::new(),
+            };
+
+            if let Err(err) = update(&mut records) {
+                return Ok(Err(err));
+            }
+
+            let record = self
+                .cipher
+                .seal(ledger_key, &serde_json::to_vec(&records)?)?;
+            if self
+                .backend
+                .compare_and_swap(ledger_key, current.as_ref(), Some(record))?
+            {
+                return Ok(Ok(()));
+            }
+        }
+    }
+}
+
+/// A [`KeyShareStore`] that keeps every record in memory and in one JSON file, which is
+/// replaced on every update
+pub struct JsonKeyShareStore {
+    path: PathBuf,
+    records: parking_lot::Mutex<HashMap<String, EncryptedRecord>>,
+}
+
+impl JsonKeyShareStore {
+    pub fn open(path: PathBuf) -> Result<Self, StoreError> {
+        let records = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            records: parking_lot::Mutex::new(records),
+        })
+    }
+
+    /// Applies `update` to a copy of the records and persists it, unless `update` returns
+    /// `false`. The records are left unchanged if they could not be written
+    fn write(
+        &self,
+        update: impl FnOnce(&mut HashMap<String, EncryptedRecord>) -> bool,
+    ) -> Result<bool, StoreError> {
+        let mut records = self.records.lock();
+        let mut updated = records.clone();
+        if !update(&mut updated) {
+            return Ok(false);
+        }
+
+        write_atomic(&self.path, &serde_json::to_vec(&updated)?)?;
+        *records = updated;
+        Ok(true)
+    }
+}
+
+impl KeyShareStore for JsonKeyShareStore {
+    fn get(&self, key: &str) -> Result<Option<EncryptedRecord>, StoreError> {
+        Ok(self.records.lock().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, record: EncryptedRecord) -> Result<(), StoreError> {
+        self.write(|records| {
+            records.insert(key.to_string(), record);
+            true
+        })
+        .map(|_| ())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StoreError> {
+        self.write(|records| records.remove(key).is_some())
+            .map(|_| ())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.records.lock().keys().cloned().collect())
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: Option<&EncryptedRecord>,
+        new: Option<EncryptedRecord>,
+    ) -> Result<bool, StoreError> {
+        self.write(|records| {
+            if records.get(key) != current {
+                return false;
+            }
+
+            match new {
+                Some(record) => records.insert(key.to_string(), record),
+                None => records.remove(key),
+            };
+            true
+        })
+    }
+}
+
+/// A [`KeyShareStore`] backed by an embedded [`redb`] database, with one transaction per
+/// operation
+pub struct RedbKeyShareStore {
+    db: redb::Database,
+}
+
+impl RedbKeyShareStore {
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let db = redb::Database::create(path).map_err(redb::Error::from)?;
+
+        // Create the table up front, so that reads never find it missing
+        let txn = db.begin_write().map_err(redb::Error::from)?;
+        txn.open_table(KEY_SHARES_TABLE)
+            .map_err(redb::Error::from)?;
+        txn.commit().map_err(redb::Error::from)?;
+
+        Ok(Self { db })
+    }
+
+    /// Replaces the record under `key` with `new` in one write transaction, if `expected`
+    /// accepts the stored record
+    fn swap_if(
+        &self,
+        key: &str,
+        expected: impl FnOnce(Option<&EncryptedRecord>) -> bool,
+        new: Option<EncryptedRecord>,
+    ) -> Result<bool, StoreError> {
+        let txn = self.db.begin_write().map_err(redb::Error::from)?;
+        {
+            let mut table = txn
+                .open_table(KEY_SHARES_TABLE)
+                .map_err(redb::Error::from)?;
+            let stored: Option<EncryptedRecord> = table
+                .get(key)
+                .map_err(redb::Error::from)?
+                .map(|record| serde_json::from_slice(record.value()))
+                .transpose()?;
+            if !expected(stored.as_ref()) {
+                return Ok(false);
+            }
+
+            match new {
+                Some(record) => {
+                    let bytes = serde_json::to_vec(&record)?;
+                    table
+                        .insert(key, bytes.as_slice())
+                        .map_err(redb::Error::from)?;
+                }
+                None => {
+                    table.remove(key).map_err(redb::Error::from)?;
+                }
+            }
+        }
+        txn.commit().map_err(redb::Error::from)?;
+
+        Ok(true)
+    }
+}
+
+impl KeyShareStore for RedbKeyShareStore {
+    fn get(&self, key: &str) -> Result<Option<EncryptedRecord>, StoreError> {
+        let txn = self.db.begin_read().map_err(redb::Error::from)?;
+        let table = txn
+            .open_table(KEY_SHARES_TABLE)
+            .map_err(redb::Error::from)?;
+        let record = table.get(key).map_err(redb::Error::from)?;
+        Ok(record
+            .map(|record| serde_json::from_slice(record.value()))
+            .transpose()?)
+    }
+
+    fn put(&self, key: &str, record: EncryptedRecord) -> Result<(), StoreError> {
+        self.swap_if(key, |_| true, Some(record)).map(|_| ())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StoreError> {
+        self.swap_if(key, |_| true, None).map(|_| ())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let txn = self.db.begin_read().map_err(redb::Error::from)?;
+        let table = txn
+            .open_table(KEY_SHARES_TABLE)
+            .map_err(redb::Error::from)?;
+        let mut keys = Vec::new();
+        for entry in table.iter().map_err(redb::Error::from)? {
+            let (key, _) = entry.map_err(redb::Error::from)?;
+            keys.push(key.value().to_string());
+        }
+
+        Ok(keys)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        current: Option<&EncryptedRecord>,
+        new: Option<EncryptedRecord>,
+    ) -> Result<bool, StoreError> {
+        self.swap_if(key, |stored| stored == current, new)
+    }
+}
+
+/// Copies every record of `from` that `to` does not have yet, returning how many there were
+fn copy_missing(from: &dyn KeyShareStore, to: &dyn KeyShareStore) -> Result<usize, StoreError> {
+    let mut copied = 0;
+    for key in from.list()? {
+        let Some(record) = from.get(&key)? else {
+            continue;
+        };
+        if to.compare_and_swap(&key, None, Some(record))? {
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Encrypts every plaintext record in the store file at `path` in place, returning how many
+/// there were
+fn migrate_plaintext(path: &Path, cipher: &StoreCipher) -> eyre::Result<usize> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let records: HashMap<String, StoredRecord> = serde_json::from_slice(&bytes)?;
+    if !records
+        .values()
+        .any(|record| matches!(record, StoredRecord::Plaintext(_)))
+    {
+        return Ok(0);
+    }
+
+    let mut migrated = 0;
+    let mut encrypted = HashMap::with_capacity(records.len());
+    for (store_key, record) in records {
+        let record = match record {
+            StoredRecord::Encrypted(record) => record,
+            StoredRecord::Plaintext(state) => {
+                migrated += 1;
+                cipher.seal(&store_key, &serde_json::to_vec(&state)?)?
+            }
+        };
+        encrypted.insert(store_key, record);
+    }
+
+    write_atomic(path, &serde_json::to_vec(&encrypted)?)?;
+
+    Ok(migrated)
+}
+
+/// Writes `bytes` next to `path` and swaps the files, so a crash never leaves a half-written
+/// file behind. The new file is flushed to disk before it replaces the old one, and the
+/// directory before and after, so that neither a crash nor a power loss can leave an empty or
+/// missing file in its place
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut tmp = std::fs::File::create(&tmp_path)?;
+    tmp.write_all(bytes)?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    sync_dir(dir)?;
+    std::fs::rename(&tmp_path, path)?;
+    sync_dir(dir)
+}
+
+/// Flushes the entries of `dir` to disk, so that files created or renamed in it survive a
+/// power loss
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::File::open(dir)?.sync_all()
+}
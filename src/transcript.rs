@@ -0,0 +1,89 @@
+use crate::signing_state_machine::WstsSigningState;
+use serde::{Deserialize, Serialize};
+
+/// Domain separator for [`SigningTranscript::digest`]
+const TRANSCRIPT_SALT: &str = "wsts-signing-transcript";
+
+/// An auditable record of how a message was signed. Every field is fixed by the protocol run,
+/// so every honest signer produces the same transcript and [`SigningTranscript::digest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningTranscript {
+    /// The call id of the keygen job that generated the key
+    pub keygen_call_id: u64,
+    /// The call id of the signing job
+    pub call_id: u64,
+    /// The group key, as a 33-byte compressed point
+    pub public_key: Vec<u8>,
+    /// The parties that signed, in ascending order of party id
+    pub signers: Vec<TranscriptSigner>,
+    /// The SHA-256 digest of the exact bytes that were signed
+    pub message_digest: [u8; 32],
+    /// The aggregate signature in the 65-byte FROST format
+    pub signature: Vec<u8>,
+}
+
+/// A signer's contribution to a [`SigningTranscript`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptSigner {
+    pub party_id: u32,
+    pub key_ids: Vec<u32>,
+    /// The compressed `D` and `E` points of the signer's public nonce
+    pub nonce_commitments: [Vec<u8>; 2],
+}
+
+impl SigningTranscript {
+    pub(crate) fn new(keygen_call_id: u64, call_id: u64, state: &WstsSigningState) -> Self {
+        let mut signers = state
+            .party_nonces
+            .iter()
+            .map(|(party_id, nonce)| TranscriptSigner {
+                party_id: *party_id,
+                key_ids: state
+                    .party_key_ids
+                    .get(party_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                nonce_commitments: [
+                    nonce.D.compress().data.to_vec(),
+                    nonce.E.compress().data.to_vec(),
+                ],
+            })
+            .collect::<Vec<_>>();
+        signers.sort_by_key(|signer| signer.party_id);
+
+        SigningTranscript {
+            keygen_call_id,
+            call_id,
+            public_key: state.public_key_frost_format.clone(),
+            signers,
+            message_digest: crate::compute_sha256_hash!(&state.message),
+            signature: state.signature_frost_format.clone(),
+        }
+    }
+
+    /// Hashes the transcript with a fixed encoding, independent of how it is serialized
+    pub fn digest(&self) -> [u8; 32] {
+        use k256::sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::default();
+        hasher.update(TRANSCRIPT_SALT);
+        hasher.update(self.keygen_call_id.to_be_bytes());
+        hasher.update(self.call_id.to_be_bytes());
+        hasher.update(&self.public_key);
+        hasher.update((self.signers.len() as u32).to_be_bytes());
+        for signer in &self.signers {
+            hasher.update(signer.party_id.to_be_bytes());
+            hasher.update((signer.key_ids.len() as u32).to_be_bytes());
+            for key_id in &signer.key_ids {
+                hasher.update(key_id.to_be_bytes());
+            }
+            for commitment in &signer.nonce_commitments {
+                hasher.update(commitment);
+            }
+        }
+        hasher.update(self.message_digest);
+        hasher.update(&self.signature);
+
+        hasher.finalize().into()
+    }
+}
@@ -1,21 +1,78 @@
 use crate::keygen::KeygenError;
+use p256k1::point::{Compressed, Point, G};
+use p256k1::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+use wsts::compute;
 
 pub fn validate_parameters(n: u32, k: u32, t: u32) -> Result<(), KeygenError> {
-    if k % n != 0 {
-        return Err(KeygenError::SetupError(format!("k({k}) % n({n}) != 0")));
-    }
-
     if k == 0 {
         return Err(KeygenError::SetupError(format!("k({k}) == 0")));
     }
 
-    if n <= t {
-        return Err(KeygenError::SetupError(format!("n({n}) <= t({t})")));
+    if k < n {
+        return Err(KeygenError::SetupError(format!(
+            "k({k}) < n({n}), every party needs at least one key id"
+        )));
+    }
+
+    if k <= t {
+        return Err(KeygenError::SetupError(format!("k({k}) <= t({t})")));
     }
 
     Ok(())
 }
 
+/// Checks that `weights` assigns at least one key id to each of the `n` parties and that the
+/// threshold `t` can be met with the resulting total number of key ids
+pub fn validate_weights(n: u32, t: u32, weights: &[u16]) -> Result<(), KeygenError> {
+    if weights.len() != n as usize {
+        return Err(KeygenError::SetupError(format!(
+            "weights.len()({}) != n({n})",
+            weights.len()
+        )));
+    }
+
+    if weights.contains(&0) {
+        return Err(KeygenError::SetupError(format!(
+            "weights({weights:?}) contains a zero weight"
+        )));
+    }
+
+    let k: u32 = weights.iter().map(|weight| *weight as u32).sum();
+    if k <= t {
+        return Err(KeygenError::SetupError(format!("k({k}) <= t({t})")));
+    }
+
+    Ok(())
+}
+
+/// Returns the key ids owned by each party. With no `weights` the `k` key ids are split as
+/// evenly as possible between the parties, defaulting to one key id per party when `k` is 0.
+/// Otherwise party `i` gets `weights[i]` key ids, and `k` must be 0 or the sum of the weights
+pub fn allocate_party_key_ids(
+    n: u32,
+    k: u32,
+    t: u32,
+    weights: &[u16],
+) -> Result<Vec<Vec<u32>>, KeygenError> {
+    if weights.is_empty() {
+        let k = if k == 0 { n } else { k };
+        validate_parameters(n, k, t)?;
+        return Ok(generate_party_key_ids(n, k));
+    }
+
+    validate_weights(n, t, weights)?;
+
+    let total: u32 = weights.iter().map(|weight| *weight as u32).sum();
+    if k != 0 && k != total {
+        return Err(KeygenError::SetupError(format!(
+            "k({k}) != sum of weights({total})"
+        )));
+    }
+
+    Ok(generate_weighted_party_key_ids(weights))
+}
+
 /// Returns a Vec of indices that denotes which indexes within the public key vector
 /// are owned by which party.
 ///
@@ -23,20 +80,23 @@ pub fn validate_parameters(n: u32, k: u32, t: u32) -> Result<(), KeygenError> {
 ///
 /// let party_key_ids: Vec<Vec<u32>> = [
 ///     [0, 1, 2].to_vec(),
-///     [3, 4].to_vec(),
-///     [5, 6, 7].to_vec(),
+///     [3, 4, 5].to_vec(),
+///     [6, 7].to_vec(),
 ///     [8, 9].to_vec(),
 /// ]
 ///
 /// In the above case, we go up from 0..=9 possible key ids since k=10, and
-/// we have 4 grouping since n=4. We need to generalize this below
+/// we have 4 grouping since n=4. The remainder of k / n is spread over the
+/// first parties, one extra key id each
 pub fn generate_party_key_ids(n: u32, k: u32) -> Vec<Vec<u32>> {
     let mut result = Vec::with_capacity(n as usize);
     let ids_per_party = k / n;
+    let remainder = k % n;
     let mut start = 0;
 
-    for _ in 0..n {
-        let end = start + ids_per_party;
+    for party in 0..n {
+        let extra = u32::from(party < remainder);
+        let end = start + ids_per_party + extra;
         let ids = (start..end).collect();
         result.push(ids);
         start = end;
@@ -44,3 +104,212 @@ pub fn generate_party_key_ids(n: u32, k: u32) -> Vec<Vec<u32>> {
 
     result
 }
+
+/// Returns a Vec of indices that denotes which indexes within the public key vector
+/// are owned by which party, where party `i` owns `weights[i]` consecutive indices.
+///
+/// E.g., for weights [3, 1, 2],
+///
+/// let party_key_ids: Vec<Vec<u32>> = [
+///     [0, 1, 2].to_vec(),
+///     [3].to_vec(),
+///     [4, 5].to_vec(),
+/// ]
+pub fn generate_weighted_party_key_ids(weights: &[u16]) -> Vec<Vec<u32>> {
+    let mut result = Vec::with_capacity(weights.len());
+    let mut start = 0;
+
+    for weight in weights {
+        let end = start + *weight as u32;
+        let ids = (start..end).collect();
+        result.push(ids);
+        start = end;
+    }
+
+    result
+}
+
+/// How a job encodes the public keys and signatures it returns
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    /// A 33-byte compressed public key, and a 65-byte signature made of the compressed
+    /// nonce commitment `R` followed by `z`, as used by `frost_secp256k1_tr`
+    #[default]
+    Frost,
+    /// A 32-byte x-only public key and a 64-byte BIP340 Schnorr signature, as used by
+    /// Bitcoin Taproot and Nostr
+    Bip340,
+    /// Like [`OutputFormat::Bip340`], but for the BIP341 Taproot output key obtained by
+    /// tweaking the group key with `TapTweak` and the given script merkle root, if any
+    Taproot(Option<[u8; 32]>),
+}
+
+impl OutputFormat {
+    /// Parses the output format parameters of a job. `format` is 0 for
+    /// [`OutputFormat::Frost`], 1 for [`OutputFormat::Bip340`] and 2 for
+    /// [`OutputFormat::Taproot`]. `taproot_merkle_root` is either empty or a 32-byte script
+    /// merkle root, and must be empty unless `format` is 2
+    pub fn new(format: u8, taproot_merkle_root: &[u8]) -> Result<Self, String> {
+        let merkle_root = match taproot_merkle_root.len() {
+            0 => None,
+            32 => Some(taproot_merkle_root.try_into().expect("32 byte merkle root")),
+            len => return Err(format!("taproot merkle root is {len} bytes, expected 32")),
+        };
+
+        match (format, merkle_root) {
+            (0, None) => Ok(OutputFormat::Frost),
+            (1, None) => Ok(OutputFormat::Bip340),
+            (2, merkle_root) => Ok(OutputFormat::Taproot(merkle_root)),
+            (0 | 1, Some(_)) => Err(format!(
+                "output format {format} does not take a taproot merkle root"
+            )),
+            _ => Err(format!("unknown output format {format}")),
+        }
+    }
+
+    /// The key signatures in this format verify against for a group key of `group_key`, and
+    /// how the parties sign for it. BIP340 keys are x-only, so the group key is first lifted to
+    /// the point with its x coordinate and an even y, which negates it if its y is odd. For
+    /// Taproot the lifted key is then tweaked as in BIP341, and negated once more if the output
+    /// key has an odd y
+    pub fn signature_key(&self, group_key: &Point) -> SignatureKey {
+        let merkle_root = match self {
+            OutputFormat::Frost => {
+                return SignatureKey {
+                    public_key: *group_key,
+                    key_sign: Scalar::from(1),
+                    tweak: Scalar::from(0),
+                    even_nonce: false,
+                }
+            }
+            OutputFormat::Bip340 => None,
+            OutputFormat::Taproot(merkle_root) => Some(*merkle_root),
+        };
+
+        let lift = even_y_sign(group_key);
+        let internal_key = lift * *group_key;
+        let tweak = merkle_root.map_or(Scalar::from(0), |merkle_root| {
+            compute::tweak(&internal_key, merkle_root)
+        });
+        let output_key = internal_key + tweak * G;
+        let negate = even_y_sign(&output_key);
+
+        SignatureKey {
+            public_key: negate * output_key,
+            key_sign: negate * lift,
+            tweak: negate * tweak,
+            even_nonce: true,
+        }
+    }
+
+    /// Encodes a 33-byte compressed group key in this format
+    pub fn encode_public_key(&self, public_key_frost_format: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            OutputFormat::Frost => Ok(public_key_frost_format.to_vec()),
+            OutputFormat::Bip340 | OutputFormat::Taproot(_) => {
+                let compressed = Compressed::try_from(public_key_frost_format)
+                    .map_err(|_| "invalid group key".to_string())?;
+                let group_key =
+                    Point::try_from(&compressed).map_err(|_| "invalid group key".to_string())?;
+
+                let public_key = self.signature_key(&group_key).public_key;
+                Ok(public_key.x().to_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Encodes a 65-byte FROST signature in this format. BIP340 and Taproot signatures must
+    /// have been produced with an even `R`, which the signing protocol takes care of
+    pub fn encode_signature(&self, signature_frost_format: &[u8]) -> Vec<u8> {
+        match self {
+            OutputFormat::Frost => signature_frost_format.to_vec(),
+            OutputFormat::Bip340 | OutputFormat::Taproot(_) => signature_frost_format[1..].to_vec(),
+        }
+    }
+}
+
+/// The key a session's signature verifies against, as given by
+/// [`OutputFormat::signature_key`]. A signer multiplies its key shares by `key_sign`, the
+/// aggregator adds the challenge times `tweak`, which no signer holds a share of, and the
+/// signature then verifies against `public_key`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureKey {
+    pub public_key: Point,
+    /// `-1` if the group key was negated on its way to `public_key`, `1` otherwise
+    pub key_sign: Scalar,
+    pub tweak: Scalar,
+    /// Whether the signature must have an even `R`, in which case the signers negate their
+    /// nonces if the aggregated one has an odd y
+    pub even_nonce: bool,
+}
+
+/// Returns `1` if `point` has an even y, and `-1` otherwise, which lifts it to even y
+fn even_y_sign(point: &Point) -> Scalar {
+    if point.has_even_y() {
+        Scalar::from(1)
+    } else {
+        -Scalar::from(1)
+    }
+}
+
+/// How the message given to a signing job is turned into the bytes that are signed
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageMode {
+    /// The message is signed as is
+    #[default]
+    Raw,
+    /// The message is a 32-byte digest computed by the caller, and is signed as is
+    Prehashed,
+    /// The BIP340 tagged hash of the message with the given domain tag is signed
+    Tagged(Vec<u8>),
+}
+
+impl MessageMode {
+    /// Parses the message mode parameters of a job. `mode` is 0 for [`MessageMode::Raw`], 1
+    /// for [`MessageMode::Prehashed`] and 2 for [`MessageMode::Tagged`]. `domain_tag` must be
+    /// empty unless `mode` is 2
+    pub fn new(mode: u8, domain_tag: Vec<u8>) -> Result<Self, String> {
+        match (mode, domain_tag.is_empty()) {
+            (0, true) => Ok(MessageMode::Raw),
+            (1, true) => Ok(MessageMode::Prehashed),
+            (2, false) => Ok(MessageMode::Tagged(domain_tag)),
+            (2, true) => Err("message mode 2 needs a domain tag".to_string()),
+            (0 | 1, false) => Err(format!("message mode {mode} does not take a domain tag")),
+            _ => Err(format!("unknown message mode {mode}")),
+        }
+    }
+
+    /// The `mode` parameter this mode is parsed from
+    pub fn id(&self) -> u8 {
+        match self {
+            MessageMode::Raw => 0,
+            MessageMode::Prehashed => 1,
+            MessageMode::Tagged(_) => 2,
+        }
+    }
+
+    /// The domain tag, which is empty unless this is [`MessageMode::Tagged`]
+    pub fn domain_tag(&self) -> &[u8] {
+        match self {
+            MessageMode::Tagged(domain_tag) => domain_tag,
+            _ => &[],
+        }
+    }
+
+    /// Returns the bytes to sign for `message`
+    pub fn signed_message(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            MessageMode::Raw => Ok(message.to_vec()),
+            MessageMode::Prehashed if message.len() == 32 => Ok(message.to_vec()),
+            MessageMode::Prehashed => Err(format!(
+                "prehashed message is {} bytes, expected 32",
+                message.len()
+            )),
+            MessageMode::Tagged(domain_tag) => {
+                // tagged_hash(tag, msg) = sha256(sha256(tag) || sha256(tag) || msg)
+                let tag_hash = crate::compute_sha256_hash!(domain_tag);
+                Ok(crate::compute_sha256_hash!(tag_hash, tag_hash, message).to_vec())
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod backup {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use wsts::Scalar;
+    use wsts_blueprint::backup::{verify_restored_share, BackupBundle, BackupError};
+    use wsts_blueprint::schema::decode_state;
+    use wsts_blueprint::store::KeyId;
+
+    const STATE_V0: &[u8] = include_bytes!("fixtures/wsts_state_v0.json");
+    const STATE_V1: &[u8] = include_bytes!("fixtures/wsts_state_v1.json");
+    const PASSPHRASE: &str = "correct horse battery staple";
+    const STORE_KEY: &str = "1/4/0";
+
+    fn sealed_bundle() -> Result<BackupBundle, Box<dyn std::error::Error>> {
+        let (state, _) = decode_state(STATE_V0)?;
+        let entries = BTreeMap::from([(STORE_KEY.to_string(), state)]);
+        Ok(BackupBundle::seal(&entries, PASSPHRASE)?)
+    }
+
+    #[test]
+    fn test_backup_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let bundle = sealed_bundle()?;
+
+        // Bundles are written to disk as JSON
+        let bundle: BackupBundle = serde_json::from_slice(&serde_json::to_vec(&bundle)?)?;
+        let restored = bundle.open(PASSPHRASE)?;
+
+        let (expected, _) = decode_state(STATE_V0)?;
+        let state = &restored[STORE_KEY];
+        assert_eq!(restored.len(), 1);
+        assert_eq!(state.party_id, expected.party_id);
+        assert_eq!(
+            state.public_key_frost_format,
+            expected.public_key_frost_format
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_rejects_wrong_passphrase() -> Result<(), Box<dyn std::error::Error>> {
+        let bundle = sealed_bundle()?;
+
+        let result = bundle.open("wrong passphrase");
+        assert!(matches!(result, Err(BackupError::EncryptionError(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_rejects_tampering() -> Result<(), Box<dyn std::error::Error>> {
+        let mut bundle = sealed_bundle()?;
+        bundle.ciphertext[0] ^= 1;
+
+        let result = bundle.open(PASSPHRASE);
+        assert!(matches!(result, Err(BackupError::EncryptionError(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_leaves_out_preprocessed_nonces() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, _) = decode_state(STATE_V1)?;
+        assert_eq!(state.nonce_pool.remaining(), 1);
+
+        let entries = BTreeMap::from([(STORE_KEY.to_string(), state)]);
+        let restored = BackupBundle::seal(&entries, PASSPHRASE)?.open(PASSPHRASE)?;
+
+        // The original store may still use these nonces, so a restored copy must not
+        assert!(restored[STORE_KEY].nonce_pool.batches.is_empty());
+        assert_eq!(restored[STORE_KEY].nonce_pool.remaining(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_restored_share() -> Result<(), Box<dyn std::error::Error>> {
+        let key_id: KeyId = STORE_KEY.parse()?;
+        let (state, _) = decode_state(STATE_V0)?;
+        let published = state.public_key_frost_format.clone();
+
+        assert!(verify_restored_share(&key_id, &state, 1, &published, None).is_ok());
+        // The keygen job may have returned the key in any output format
+        assert!(verify_restored_share(&key_id, &state, 1, &published[1..], None).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_restored_share_rejects_mismatches() -> Result<(), Box<dyn std::error::Error>> {
+        let key_id: KeyId = STORE_KEY.parse()?;
+        let (state, _) = decode_state(STATE_V0)?;
+        let published = state.public_key_frost_format.clone();
+        let is_mismatch = |result: Result<(), BackupError>| matches!(result, Err(BackupError::Mismatch { key, .. }) if key == STORE_KEY);
+
+        // Another operator's share
+        assert!(is_mismatch(verify_restored_share(
+            &key_id, &state, 2, &published, None
+        )));
+
+        // Another key than the keygen job published
+        let mut other = published.clone();
+        other[1] ^= 1;
+        assert!(is_mismatch(verify_restored_share(
+            &key_id, &state, 1, &other, None
+        )));
+
+        // A share that does not match its dealer's commitment
+        let mut tampered = state.clone();
+        let share = tampered
+            .shares
+            .get_mut(&0)
+            .and_then(|shares| shares.get_mut(&1))
+            .expect("share from party 0");
+        *share += Scalar::from(1u32);
+        assert!(is_mismatch(verify_restored_share(
+            &key_id, &tampered, 1, &published, None
+        )));
+
+        // A dealer's commitment is missing, so the rest no longer add up to the group key
+        let mut missing = state.clone();
+        missing.poly_commitments.remove(&2);
+        assert!(is_mismatch(verify_restored_share(
+            &key_id, &missing, 1, &published, None
+        )));
+
+        // An empty commitment is rejected rather than read past its end
+        let mut empty = state;
+        empty
+            .poly_commitments
+            .get_mut(&2)
+            .expect("commitment from party 2")
+            .A
+            .clear();
+        assert!(is_mismatch(verify_restored_share(
+            &key_id, &empty, 1, &published, None
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_restored_share_rejects_tampered_private_keys(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key_id: KeyId = STORE_KEY.parse()?;
+        let (state, _) = decode_state(STATE_V0)?;
+        let published = state.public_key_frost_format.clone();
+        let with_party = |party| {
+            let mut tampered = state.clone();
+            tampered.party = Arc::new(parking_lot::Mutex::new(Some(party)));
+            tampered
+        };
+        let is_mismatch = |state| matches!(verify_restored_share(&key_id, &state, 1, &published, None), Err(BackupError::Mismatch { key, .. }) if key == STORE_KEY);
+
+        // The shares and commitments are intact, but a private key is not their sum
+        let mut party = state.party_state().expect("saved party");
+        let private_key = party
+            .private_keys
+            .get_mut(&party.key_ids[0])
+            .expect("private key");
+        *private_key += Scalar::from(1u32);
+        assert!(is_mismatch(with_party(party)));
+
+        // A private key is missing
+        let mut party = state.party_state().expect("saved party");
+        party.private_keys.remove(&party.key_ids[0]);
+        assert!(is_mismatch(with_party(party)));
+
+        // The saved party holds another party's key ids
+        let mut party = state.party_state().expect("saved party");
+        party.key_ids = vec![0];
+        assert!(is_mismatch(with_party(party)));
+
+        // The untouched party still verifies
+        let party = state.party_state().expect("saved party");
+        assert!(!is_mismatch(with_party(party)));
+
+        Ok(())
+    }
+}
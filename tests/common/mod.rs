@@ -0,0 +1,336 @@
+//! Runs the protocol state machines between in-process parties, so that tests can play a
+//! faulty party by rewriting the messages it sends
+#![allow(dead_code)]
+
+use blueprint_sdk::crypto::k256::{K256Ecdsa, K256VerifyingKey};
+use blueprint_sdk::crypto::KeyType;
+use blueprint_sdk::networking::GossipMsgKeyPair;
+use blueprint_sdk::tokio;
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
+use round_based::simulation::{MockedDelivery, Simulation};
+use round_based::{Delivery, Incoming, MpcParty, Outgoing};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wsts::v2::Party;
+use wsts_blueprint::keygen::KeygenError;
+use wsts_blueprint::keygen_state_machine::{self, WstsState};
+use wsts_blueprint::preprocessing_state_machine::{
+    self, NonceBatch, NoncePool, NonceSlot, NonceStatus, PreprocessedNonce,
+};
+use wsts_blueprint::signing::SigningError;
+use wsts_blueprint::signing_state_machine::{
+    self, NonceLedger, PreprocessedNonces, WstsSigningState,
+};
+use wsts_blueprint::utils::OutputFormat;
+
+/// How long a protocol run may take before the test fails instead of hanging
+const PROTOCOL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The keygen session id every simulated keygen runs with
+pub const KEYGEN_CTX: [u8; 32] = [7u8; 32];
+
+type Rewrite<M> = Box<dyn FnMut(&mut M) + Send>;
+type Incomings<M> = Pin<
+    Box<
+        dyn Stream<Item = Result<Incoming<M>, <MockedDelivery<M> as Delivery<M>>::ReceiveError>>
+            + Send,
+    >,
+>;
+type Outgoings<M> =
+    Pin<Box<dyn Sink<Outgoing<M>, Error = <MockedDelivery<M> as Delivery<M>>::SendError> + Send>>;
+type Network<M> = MpcParty<M, (Incomings<M>, Outgoings<M>)>;
+
+/// The operators of a simulated service, each with its own identity key
+pub struct Operators {
+    pub identities: Vec<GossipMsgKeyPair>,
+    pub parties: BTreeMap<u16, K256VerifyingKey>,
+}
+
+impl Operators {
+    pub fn new(n: u16) -> Self {
+        let identities = (0..n)
+            .map(|_| K256Ecdsa::generate_with_seed(None).expect("identity key"))
+            .collect::<Vec<_>>();
+        let parties = identities
+            .iter()
+            .enumerate()
+            .map(|(i, identity)| (i as u16, identity.public()))
+            .collect();
+
+        Self {
+            identities,
+            parties,
+        }
+    }
+}
+
+/// The misbehaviour of the faulty parties of a protocol run. The outcome of a run is only
+/// collected from the other parties, since a faulty party may wait forever for honest parties
+/// that already aborted
+pub struct Faults<M> {
+    faulty: BTreeSet<u16>,
+    outgoing: HashMap<u16, Rewrite<M>>,
+    incoming: HashMap<u16, (u16, Rewrite<M>)>,
+}
+
+impl<M> Faults<M> {
+    pub fn none() -> Self {
+        Self {
+            faulty: BTreeSet::new(),
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+        }
+    }
+
+    /// `faulty` rewrites every message it sends with `rewrite`
+    pub fn sends(mut self, faulty: u16, rewrite: impl FnMut(&mut M) + Send + 'static) -> Self {
+        self.faulty.insert(faulty);
+        self.outgoing.insert(faulty, Box::new(rewrite));
+        self
+    }
+
+    /// `faulty` sends `victim` every message rewritten by `rewrite`, and everyone else the
+    /// original message
+    pub fn sends_to(
+        mut self,
+        faulty: u16,
+        victim: u16,
+        rewrite: impl FnMut(&mut M) + Send + 'static,
+    ) -> Self {
+        self.faulty.insert(faulty);
+        self.incoming.insert(victim, (faulty, Box::new(rewrite)));
+        self
+    }
+
+    fn connect(&mut self, simulation: &mut Simulation<M>, party: u16) -> Network<M>
+    where
+        M: Clone + Send + Unpin + 'static,
+    {
+        // The simulation hands a party its own broadcasts, which the real network does not
+        let (incoming, outgoing) = simulation.connect_new_party().split();
+        let incoming = incoming.filter(move |incoming| {
+            future::ready(
+                incoming
+                    .as_ref()
+                    .map_or(true, |incoming| incoming.sender != party),
+            )
+        });
+
+        let incoming: Incomings<M> = match self.incoming.remove(&party) {
+            Some((faulty, mut rewrite)) => Box::pin(incoming.map(move |incoming| {
+                incoming.map(|mut incoming| {
+                    if incoming.sender == faulty {
+                        rewrite(&mut incoming.msg);
+                    }
+                    incoming
+                })
+            })),
+            None => Box::pin(incoming),
+        };
+
+        let outgoing: Outgoings<M> = match self.outgoing.remove(&party) {
+            Some(mut rewrite) => Box::pin(outgoing.with(move |mut outgoing: Outgoing<M>| {
+                rewrite(&mut outgoing.msg);
+                future::ready(Ok(outgoing))
+            })),
+            None => Box::pin(outgoing),
+        };
+
+        MpcParty::connected((incoming, outgoing))
+    }
+}
+
+/// Rewrites a protocol message through its JSON form, since the fields of protocol messages
+/// are private
+pub fn edit<T: Serialize + DeserializeOwned>(msg: &mut T, edit: impl FnOnce(&mut Value)) {
+    let mut value = serde_json::to_value(&*msg).expect("serializable message");
+    edit(&mut value);
+    *msg = serde_json::from_value(value).expect("edited message");
+}
+
+/// Runs every party to completion, returning the outcome of every honest party
+async fn run<T: Send + 'static>(
+    runs: Vec<tokio::task::JoinHandle<T>>,
+    faulty: &BTreeSet<u16>,
+) -> BTreeMap<u16, T> {
+    let mut outcomes = BTreeMap::new();
+    for (party, run) in runs.into_iter().enumerate() {
+        let party = party as u16;
+        if faulty.contains(&party) {
+            continue;
+        }
+
+        let outcome = tokio::time::timeout(PROTOCOL_TIMEOUT, run)
+            .await
+            .unwrap_or_else(|_| panic!("party {party} did not finish"))
+            .expect("protocol task");
+        outcomes.insert(party, outcome);
+    }
+
+    outcomes
+}
+
+/// Runs keygen between `operators`, where party `i` owns `key_ids[i]`, returning the outcome
+/// of every honest party
+pub async fn keygen(
+    operators: &Operators,
+    t: u32,
+    key_ids: &[Vec<u32>],
+    mut faults: Faults<keygen_state_machine::Msg>,
+) -> BTreeMap<u16, Result<WstsState, KeygenError>> {
+    let n = key_ids.len();
+    let k = key_ids.iter().map(Vec::len).sum::<usize>() as u32;
+    let key_ids = Arc::new(key_ids.to_vec());
+    let parties = Arc::new(operators.parties.clone());
+
+    let mut simulation = Simulation::new();
+    let mut runs = Vec::new();
+    for (party_id, identity) in operators.identities.iter().cloned().enumerate() {
+        let network = faults.connect(&mut simulation, party_id as u16);
+        let key_ids = key_ids.clone();
+        let parties = parties.clone();
+        runs.push(tokio::spawn(async move {
+            let mut rng = rand::rngs::OsRng;
+            let mut party = Party::new(
+                party_id as u32,
+                &key_ids[party_id],
+                n as u32,
+                k,
+                t,
+                &mut rng,
+            );
+            keygen_state_machine::wsts_protocol(
+                network, &mut party, &key_ids, n, KEYGEN_CTX, &identity, &parties, &mut rng,
+            )
+            .await
+        }));
+    }
+
+    let faulty = faults.faulty.clone();
+    run(runs, &faulty).await
+}
+
+/// Runs keygen with every party honest, returning every party's share
+pub async fn honest_keygen(operators: &Operators, t: u32, key_ids: &[Vec<u32>]) -> Vec<WstsState> {
+    keygen(operators, t, key_ids, Faults::none())
+        .await
+        .into_values()
+        .map(|state| state.expect("keygen"))
+        .collect()
+}
+
+/// A nonce ledger that does not persist the nonces of a session
+pub struct FreshNonces;
+
+impl NonceLedger for FreshNonces {
+    fn advance(
+        &self,
+        _public_nonces: &[wsts::common::PublicNonce],
+        _status: NonceStatus,
+    ) -> Result<(), SigningError> {
+        Ok(())
+    }
+}
+
+/// Preprocessed nonces held in memory, taken out exactly as the key store does
+#[derive(Default)]
+pub struct PoolNonces(pub Mutex<NoncePool>);
+
+impl PreprocessedNonces for PoolNonces {
+    fn take_next(&self, count: usize) -> Vec<PreprocessedNonce> {
+        let mut pool = self.0.lock().expect("pool");
+        if pool.remaining() < count {
+            return Vec::new();
+        }
+
+        (0..count).filter_map(|_| pool.take()).collect()
+    }
+
+    fn take_slots(&self, slots: &[NonceSlot]) -> Option<Vec<PreprocessedNonce>> {
+        self.0.lock().expect("pool").take_slots(slots)
+    }
+}
+
+/// Runs nonce preprocessing with the shares in `states`, where `states[i]` is run as party
+/// `i`, returning the outcome of every honest party
+pub async fn preprocess(
+    states: &[WstsState],
+    batch_id: u64,
+    count: usize,
+    mut faults: Faults<preprocessing_state_machine::Msg>,
+) -> BTreeMap<u16, Result<NonceBatch, SigningError>> {
+    let mut simulation = Simulation::new();
+    let mut runs = Vec::new();
+    for (party, state) in states.iter().cloned().enumerate() {
+        let network = faults.connect(&mut simulation, party as u16);
+        runs.push(tokio::spawn(async move {
+            preprocessing_state_machine::wsts_preprocessing_protocol(
+                network,
+                &state,
+                batch_id,
+                count,
+                &mut rand::rngs::OsRng,
+            )
+            .await
+        }));
+    }
+
+    let faulty = faults.faulty.clone();
+    run(runs, &faulty).await
+}
+
+/// Signs `messages` with the shares in `states`, where `states[i]` is run as party `i`,
+/// returning the outcome of every honest party
+pub async fn sign(
+    states: &[WstsState],
+    messages: &[Vec<u8>],
+    format: OutputFormat,
+    coordinator: u32,
+    faults: Faults<signing_state_machine::Msg>,
+) -> BTreeMap<u16, Result<Vec<WstsSigningState>, SigningError>> {
+    let pools = states.iter().map(|_| Arc::default()).collect::<Vec<_>>();
+    sign_with_pools(states, &pools, messages, format, coordinator, faults).await
+}
+
+/// Like [`sign`], where party `i` takes its preprocessed nonces from `pools[i]`
+pub async fn sign_with_pools(
+    states: &[WstsState],
+    pools: &[Arc<PoolNonces>],
+    messages: &[Vec<u8>],
+    format: OutputFormat,
+    coordinator: u32,
+    mut faults: Faults<signing_state_machine::Msg>,
+) -> BTreeMap<u16, Result<Vec<WstsSigningState>, SigningError>> {
+    let mut simulation = Simulation::new();
+    let mut runs = Vec::new();
+    for (party, (state, pool)) in states
+        .iter()
+        .cloned()
+        .zip(pools.iter().cloned())
+        .enumerate()
+    {
+        let network = faults.connect(&mut simulation, party as u16);
+        let messages = messages.to_vec();
+        runs.push(tokio::spawn(async move {
+            signing_state_machine::wsts_signing_protocol(
+                network,
+                &state,
+                messages,
+                format,
+                coordinator,
+                &*pool,
+                &FreshNonces,
+                &mut rand::rngs::OsRng,
+            )
+            .await
+        }));
+    }
+
+    let faulty = faults.faulty.clone();
+    run(runs, &faulty).await
+}
@@ -0,0 +1,208 @@
+mod common;
+
+#[cfg(test)]
+mod keygen {
+    use crate::common::{self, Faults, Operators, KEYGEN_CTX};
+    use blueprint_sdk::crypto::k256::K256Ecdsa;
+    use blueprint_sdk::crypto::KeyType;
+    use blueprint_sdk::tokio;
+    use p256k1::point::Point;
+    use p256k1::scalar::Scalar;
+    use serde_json::json;
+    use std::collections::{BTreeMap, BTreeSet};
+    use wsts::v2::Party;
+    use wsts_blueprint::keygen::{BlameReason, KeygenError};
+    use wsts_blueprint::keygen_state_machine::{
+        broadcast_signing_digest, commitment_digest, Msg, WstsState,
+    };
+    use wsts_blueprint::utils::OutputFormat;
+
+    const KEY_IDS: [&[u32]; 3] = [&[0], &[1], &[2]];
+
+    fn key_ids() -> Vec<Vec<u32>> {
+        KEY_IDS.iter().map(|key_ids| key_ids.to_vec()).collect()
+    }
+
+    fn expect_blame(
+        outcomes: BTreeMap<u16, Result<WstsState, KeygenError>>,
+        culprits: Vec<u32>,
+        reason: BlameReason,
+    ) {
+        assert!(!outcomes.is_empty());
+        for (party, outcome) in outcomes {
+            match outcome {
+                Err(KeygenError::Blame {
+                    culprits: blamed,
+                    reason: blamed_for,
+                }) => {
+                    assert_eq!(blamed, culprits, "party {party} blamed the wrong parties");
+                    assert_eq!(
+                        blamed_for, reason,
+                        "party {party} blamed for the wrong reason"
+                    );
+                }
+                Err(err) => panic!("party {party} expected {culprits:?} to be blamed, got {err}"),
+                Ok(_) => panic!("party {party} expected {culprits:?} to be blamed, but succeeded"),
+            }
+        }
+    }
+
+    /// Checks that every party finished with the same group key, from exactly `dealers`
+    fn expect_group_key(
+        outcomes: BTreeMap<u16, Result<WstsState, KeygenError>>,
+        dealers: &[u32],
+    ) -> Vec<WstsState> {
+        let states = outcomes
+            .into_iter()
+            .map(|(party, outcome)| {
+                outcome.unwrap_or_else(|err| panic!("party {party} failed keygen: {err}"))
+            })
+            .collect::<Vec<_>>();
+
+        for state in &states {
+            let qualified = state
+                .poly_commitments
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>();
+            assert_eq!(qualified, dealers.iter().copied().collect());
+
+            let group_key = dealers
+                .iter()
+                .map(|dealer| state.poly_commitments[dealer].A[0])
+                .fold(Point::new(), |group_key, public| group_key + public);
+            assert_eq!(
+                state.public_key_frost_format,
+                group_key.compress().data.to_vec()
+            );
+            assert_eq!(
+                state.public_key_frost_format,
+                states[0].public_key_frost_format
+            );
+        }
+
+        states
+    }
+
+    #[tokio::test]
+    async fn test_equivocating_dealer_is_blamed() {
+        let operators = Operators::new(3);
+
+        // Party 2 sends party 0 a different commitment, properly signed, than everyone else
+        let mut identity = operators.identities[2].clone();
+        let mut rng = rand::rngs::OsRng;
+        let other_commitment = Party::new(2, &[2], 3, 3, 2, &mut rng).get_poly_commitment(&mut rng);
+        let faults = Faults::none().sends_to(2, 0, move |msg: &mut Msg| {
+            if !matches!(msg, Msg::KeygenBroadcast(_)) {
+                return;
+            }
+
+            common::edit(msg, |msg| {
+                msg["KeygenBroadcast"]["poly_commitment"] =
+                    serde_json::to_value(&other_commitment).expect("commitment");
+            });
+            let Msg::KeygenBroadcast(broadcast) = msg else {
+                unreachable!()
+            };
+            let digest = broadcast_signing_digest(&KEYGEN_CTX, &commitment_digest(broadcast));
+            let signature = K256Ecdsa::sign_with_secret(&mut identity, &digest).expect("sign");
+            common::edit(msg, |msg| {
+                msg["KeygenBroadcast"]["signature"] =
+                    serde_json::to_value(&signature).expect("signature");
+            });
+        });
+
+        let outcomes = common::keygen(&operators, 2, &key_ids(), faults).await;
+        expect_blame(outcomes, vec![2], BlameReason::Equivocation);
+    }
+
+    #[tokio::test]
+    async fn test_impersonated_complaint_is_blamed() {
+        let operators = Operators::new(3);
+
+        // Party 1 complains in party 2's name
+        let faults = Faults::none().sends(1, |msg: &mut Msg| {
+            if let Msg::KeygenComplaint(_) = msg {
+                common::edit(msg, |msg| msg["KeygenComplaint"]["source"] = json!(2));
+            }
+        });
+
+        let outcomes = common::keygen(&operators, 2, &key_ids(), faults).await;
+        expect_blame(outcomes, vec![1], BlameReason::Impersonation);
+    }
+
+    #[tokio::test]
+    async fn test_bad_share_is_replaced_by_justification() {
+        let operators = Operators::new(3);
+
+        // Party 0 receives a bad share from party 2, but party 2 reveals the share it really
+        // sent, which verifies
+        let faults = Faults::none().sends_to(2, 0, |msg: &mut Msg| {
+            if let Msg::KeygenShares(_) = msg {
+                common::edit(msg, |msg| {
+                    msg["KeygenShares"]["shares"]["0"] =
+                        serde_json::to_value(Scalar::from(1)).expect("scalar");
+                });
+            }
+        });
+
+        let outcomes = common::keygen(&operators, 2, &key_ids(), faults).await;
+        let states = expect_group_key(outcomes, &[0, 1, 2]);
+        assert_ne!(states[0].shares[&2][&0], Scalar::from(1));
+    }
+
+    #[tokio::test]
+    async fn test_false_complaint_is_answered() {
+        let operators = Operators::new(3);
+
+        // Party 0 accuses party 1, whose shares were valid
+        let faults = Faults::none().sends(0, |msg: &mut Msg| {
+            if let Msg::KeygenComplaint(_) = msg {
+                common::edit(msg, |msg| msg["KeygenComplaint"]["accused"] = json!([1]));
+            }
+        });
+
+        let outcomes = common::keygen(&operators, 2, &key_ids(), faults).await;
+        assert_eq!(outcomes.len(), 2);
+        expect_group_key(outcomes, &[0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_justification_disqualifies_dealer() {
+        let operators = Operators::new(3);
+
+        // Party 2 sends party 0 a bad share, and reveals the same bad share when party 0
+        // complains
+        let faults = Faults::none().sends(2, |msg: &mut Msg| match msg {
+            Msg::KeygenShares(_) => common::edit(msg, |msg| {
+                if msg["KeygenShares"]["destination"] == 0 {
+                    msg["KeygenShares"]["shares"]["0"] =
+                        serde_json::to_value(Scalar::from(1)).expect("scalar");
+                }
+            }),
+            Msg::KeygenJustification(_) => {
+                common::edit(msg, |msg| {
+                    msg["KeygenJustification"]["shares"]["0"]["0"] =
+                        serde_json::to_value(Scalar::from(1)).expect("scalar");
+                });
+            }
+            _ => {}
+        });
+
+        let outcomes = common::keygen(&operators, 2, &key_ids(), faults).await;
+        assert_eq!(outcomes.len(), 2);
+
+        // Parties 0 and 1 still hold a key without party 2, and can sign with it
+        let states = expect_group_key(outcomes, &[0, 1]);
+        let signed = common::sign(
+            &states,
+            &[b"message".to_vec()],
+            OutputFormat::Frost,
+            0,
+            Faults::none(),
+        )
+        .await;
+        assert_eq!(signed.len(), 2);
+        assert!(signed.into_values().all(|outcome| outcome.is_ok()));
+    }
+}
@@ -0,0 +1,301 @@
+#[cfg(test)]
+mod policy {
+    use blueprint_sdk::crypto::k256::K256Ecdsa;
+    use blueprint_sdk::crypto::KeyType;
+    use blueprint_sdk::tangle_subxt::subxt::utils::AccountId32;
+    use wsts_blueprint::policy::{
+        PolicyEngine, PolicyViolation, RateLimit, RateLimitPermit, SigningPolicy, SigningRefusal,
+    };
+    use wsts_blueprint::utils::MessageMode;
+
+    const STORE_KEY: &str = "1/4/0";
+
+    fn engine(policy: SigningPolicy) -> PolicyEngine {
+        PolicyEngine::new(policy).expect("valid policy")
+    }
+
+    fn engine_with_tags(tags: &[&[u8]]) -> PolicyEngine {
+        engine(SigningPolicy {
+            allowed_domain_tags: tags.iter().map(hex::encode).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_empty_policy_accepts_everything() {
+        let engine = engine(SigningPolicy::default());
+        let result = engine.check(STORE_KEY, None, &MessageMode::Raw, &[vec![0; 1024]]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_caller_allowlist() {
+        let allowed = AccountId32([1; 32]);
+        let engine = engine(SigningPolicy {
+            allowed_callers: vec![hex::encode(allowed.0)],
+            ..Default::default()
+        });
+        let messages = [b"hello".to_vec()];
+
+        assert!(engine
+            .check(STORE_KEY, Some(&allowed), &MessageMode::Raw, &messages)
+            .is_ok());
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &MessageMode::Raw, &messages),
+            Err(PolicyViolation::UnknownCaller)
+        ));
+        assert!(matches!(
+            engine.check(STORE_KEY, Some(&AccountId32([2; 32])), &MessageMode::Raw, &messages),
+            Err(PolicyViolation::CallerNotAllowed(caller)) if caller == hex::encode([2; 32])
+        ));
+    }
+
+    #[test]
+    fn test_allowed_prefixes() {
+        let engine = engine(SigningPolicy {
+            allowed_prefixes: vec![hex::encode(b"TapR"), "deadbeef".to_string()],
+            ..Default::default()
+        });
+
+        let accepted = [b"TapRoot".to_vec(), vec![0xde, 0xad, 0xbe, 0xef, 0x00]];
+        assert!(engine
+            .check(STORE_KEY, None, &MessageMode::Raw, &accepted)
+            .is_ok());
+
+        // A single message without an allowed prefix rejects the whole request
+        let rejected = [b"TapRoot".to_vec(), b"Other".to_vec()];
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &MessageMode::Raw, &rejected),
+            Err(PolicyViolation::PrefixNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_prefixes_apply_to_the_unhashed_message() {
+        let engine = engine(SigningPolicy {
+            allowed_prefixes: vec![hex::encode(b"TapR")],
+            ..Default::default()
+        });
+        let mode = MessageMode::Tagged(b"TapLeaf".to_vec());
+
+        assert!(engine
+            .check(STORE_KEY, None, &mode, &[b"TapRoot".to_vec()])
+            .is_ok());
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &mode, &[b"Other".to_vec()]),
+            Err(PolicyViolation::PrefixNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_max_message_len() {
+        let engine = engine(SigningPolicy {
+            max_message_len: Some(32),
+            ..Default::default()
+        });
+
+        assert!(engine
+            .check(STORE_KEY, None, &MessageMode::Raw, &[vec![0; 32]])
+            .is_ok());
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &MessageMode::Raw, &[vec![0; 33]]),
+            Err(PolicyViolation::MessageTooLong { len: 33, max: 32 })
+        ));
+    }
+
+    #[test]
+    fn test_allowed_domain_tags() {
+        let engine = engine(SigningPolicy {
+            allowed_domain_tags: vec![hex::encode(b"TapLeaf")],
+            ..Default::default()
+        });
+        let messages = [b"hello".to_vec()];
+
+        assert!(engine
+            .check(
+                STORE_KEY,
+                None,
+                &MessageMode::Tagged(b"TapLeaf".to_vec()),
+                &messages
+            )
+            .is_ok());
+        for mode in [
+            MessageMode::Raw,
+            MessageMode::Prehashed,
+            MessageMode::Tagged(b"TapBranch".to_vec()),
+        ] {
+            assert!(matches!(
+                engine.check(STORE_KEY, None, &mode, &messages),
+                Err(PolicyViolation::DomainTagNotAllowed)
+            ));
+        }
+    }
+
+    fn rate_limited() -> PolicyEngine {
+        engine(SigningPolicy {
+            rate_limit: Some(RateLimit {
+                max_messages: 3,
+                window_secs: 3600,
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let engine = rate_limited();
+        let message = b"hello".to_vec();
+        let sign = |messages: &[Vec<u8>]| {
+            engine
+                .check(STORE_KEY, None, &MessageMode::Raw, messages)
+                .map(RateLimitPermit::charge)
+        };
+
+        assert!(sign(&[message.clone(), message.clone()]).is_ok());
+
+        // A batch that would go over the limit is rejected as a whole and does not count
+        assert!(matches!(
+            sign(&[message.clone(), message.clone()]),
+            Err(PolicyViolation::RateLimited {
+                max_messages: 3,
+                window_secs: 3600
+            })
+        ));
+        assert!(sign(std::slice::from_ref(&message)).is_ok());
+        assert!(matches!(
+            sign(std::slice::from_ref(&message)),
+            Err(PolicyViolation::RateLimited { .. })
+        ));
+
+        // Each key has its own limit
+        assert!(engine
+            .check("1/5/0", None, &MessageMode::Raw, &[message])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rate_limit_only_counts_signed_messages() {
+        let engine = rate_limited();
+        let messages = [b"hello".to_vec(), b"hello".to_vec()];
+
+        // Messages being signed are held against the limit, so a concurrent request cannot
+        // go over it
+        let permit = engine
+            .check(STORE_KEY, None, &MessageMode::Raw, &messages)
+            .expect("within the limit");
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &MessageMode::Raw, &messages),
+            Err(PolicyViolation::RateLimited { .. })
+        ));
+
+        // A session that failed to sign releases them
+        drop(permit);
+        for _ in 0..3 {
+            let permit = engine
+                .check(STORE_KEY, None, &MessageMode::Raw, &messages)
+                .expect("released");
+            drop(permit);
+        }
+
+        // Once charged, they count until the window passes
+        engine
+            .check(STORE_KEY, None, &MessageMode::Raw, &messages)
+            .expect("within the limit")
+            .charge();
+        assert!(matches!(
+            engine.check(STORE_KEY, None, &MessageMode::Raw, &messages),
+            Err(PolicyViolation::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn test_psbt_sighashes() {
+        let sighashes = [vec![0; 32], vec![1; 32]];
+
+        // Sighashes are computed by the operators, so caller prefixes do not apply to them
+        let prefixed = engine(SigningPolicy {
+            allowed_prefixes: vec![hex::encode(b"TapR")],
+            ..Default::default()
+        });
+        assert!(prefixed.check_psbt(STORE_KEY, None, &sighashes).is_ok());
+        assert!(matches!(
+            prefixed.check(STORE_KEY, None, &MessageMode::Prehashed, &sighashes),
+            Err(PolicyViolation::PrefixNotAllowed)
+        ));
+
+        // They are BIP340 tagged hashes, and only accepted if their tag is allowed
+        let tagged = engine_with_tags(&[b"TapLeaf"]);
+        assert!(matches!(
+            tagged.check_psbt(STORE_KEY, None, &sighashes),
+            Err(PolicyViolation::DomainTagNotAllowed)
+        ));
+        let tagged = engine_with_tags(&[b"TapLeaf", b"TapSighash"]);
+        assert!(tagged.check_psbt(STORE_KEY, None, &sighashes).is_ok());
+
+        // The other rules apply as usual
+        let restricted = engine(SigningPolicy {
+            max_message_len: Some(16),
+            allowed_callers: vec![hex::encode([1; 32])],
+            ..Default::default()
+        });
+        assert!(matches!(
+            restricted.check_psbt(STORE_KEY, None, &sighashes),
+            Err(PolicyViolation::UnknownCaller)
+        ));
+        assert!(matches!(
+            restricted.check_psbt(STORE_KEY, Some(&AccountId32([1; 32])), &sighashes),
+            Err(PolicyViolation::MessageTooLong { len: 32, max: 16 })
+        ));
+
+        assert!(matches!(
+            rate_limited().check_psbt(STORE_KEY, None, &vec![vec![0; 32]; 4]),
+            Err(PolicyViolation::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_invalid_policy() {
+        let result = PolicyEngine::new(SigningPolicy {
+            allowed_prefixes: vec!["not hex".to_string()],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+
+        let result = PolicyEngine::new(SigningPolicy {
+            allowed_callers: vec![hex::encode([1; 20])],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_refusal_signature_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let identity = K256Ecdsa::generate_with_seed(None)?;
+        let public_key = K256Ecdsa::public_from_secret(&identity);
+        let other = K256Ecdsa::public_from_secret(&K256Ecdsa::generate_with_seed(None)?);
+
+        let refusal = SigningRefusal::new(
+            &identity,
+            2,
+            [7; 32],
+            PolicyViolation::PrefixNotAllowed.to_string(),
+        )?;
+        assert!(refusal.verify(&public_key));
+        assert!(!refusal.verify(&other));
+
+        // Every field is covered by the signature
+        let mut tampered = refusal.clone();
+        tampered.party_id = 3;
+        assert!(!tampered.verify(&public_key));
+
+        let mut tampered = refusal.clone();
+        tampered.session_id = [8; 32];
+        assert!(!tampered.verify(&public_key));
+
+        let mut tampered = refusal;
+        tampered.reason = "message is too long".to_string();
+        assert!(!tampered.verify(&public_key));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod schema {
+    use p256k1::point::Point;
+    use wsts_blueprint::schema::{
+        decode_state, encode_state, KeyShareEnvelope, SchemaError, CURRENT_SCHEMA_VERSION,
+    };
+
+    const STATE_V0: &[u8] = include_bytes!("fixtures/wsts_state_v0.json");
+    const STATE_V1: &[u8] = include_bytes!("fixtures/wsts_state_v1.json");
+
+    #[test]
+    fn test_load_unversioned_state() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, version) = decode_state(STATE_V0)?;
+
+        assert_eq!(version, 0);
+        assert_eq!(state.party_id, 1);
+        assert_eq!(state.n_signers, 3);
+        assert_eq!(state.key_ids[&1], vec![1]);
+        assert_eq!(state.public_key_frost_format.len(), 33);
+        assert!(state.nonce_pool.batches.is_empty());
+
+        // The fixtures hold party 1's share of a real 2-of-3 keygen
+        let party = state.party_state().expect("saved party");
+        assert_eq!(state.poly_commitments.len(), 3);
+        assert!(state.poly_commitments.values().all(|c| c.verify()));
+        assert_eq!(state.shares.len(), 3);
+
+        let group_key = state
+            .poly_commitments
+            .values()
+            .fold(Point::new(), |acc, commitment| acc + commitment.A[0]);
+        assert_eq!(party.group_key, group_key);
+        assert_eq!(
+            state.public_key_frost_format,
+            group_key.compress().data.to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_v1_state() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, version) = decode_state(STATE_V1)?;
+
+        assert_eq!(version, 1);
+        assert_eq!(state.party_id, 1);
+        assert_eq!(state.nonce_pool.batches.len(), 1);
+        assert_eq!(state.nonce_pool.batches[0].batch_id, 7);
+        assert_eq!(state.nonce_pool.batches[0].public_nonces.len(), 3);
+        assert_eq!(state.nonce_pool.remaining(), 1);
+        assert!(state.party_state().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrated_state_is_stored_in_current_version() -> Result<(), Box<dyn std::error::Error>>
+    {
+        for fixture in [STATE_V0, STATE_V1] {
+            let (state, _) = decode_state(fixture)?;
+            let encoded = encode_state(&state)?;
+
+            let envelope: KeyShareEnvelope = serde_json::from_slice(&encoded)?;
+            assert_eq!(envelope.version, CURRENT_SCHEMA_VERSION);
+
+            let (reloaded, version) = decode_state(&encoded)?;
+            assert_eq!(version, CURRENT_SCHEMA_VERSION);
+            assert_eq!(
+                reloaded.public_key_frost_format,
+                state.public_key_frost_format
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_newer_version() -> Result<(), Box<dyn std::error::Error>> {
+        let mut envelope: KeyShareEnvelope = serde_json::from_slice(STATE_V1)?;
+        envelope.version = CURRENT_SCHEMA_VERSION + 1;
+
+        let result = decode_state(&serde_json::to_vec(&envelope)?);
+        assert!(matches!(
+            result,
+            Err(SchemaError::UnsupportedVersion(version)) if version == CURRENT_SCHEMA_VERSION + 1
+        ));
+
+        Ok(())
+    }
+}
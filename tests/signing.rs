@@ -0,0 +1,259 @@
+mod common;
+
+#[cfg(test)]
+mod signing {
+    use crate::common::{self, Faults, Operators, PoolNonces};
+    use bitcoin::secp256k1::{schnorr, Message, Secp256k1, XOnlyPublicKey};
+    use blueprint_sdk::tokio;
+    use p256k1::scalar::Scalar;
+    use serde_json::json;
+    use std::sync::Arc;
+    use wsts::common::{Nonce, PublicNonce};
+    use wsts_blueprint::preprocessing_state_machine::{
+        self, advance_ledger, NoncePool, NonceRecord, NonceStatus, NONCE_LEDGER_TTL,
+    };
+    use wsts_blueprint::signing::SigningError;
+    use wsts_blueprint::signing_state_machine::Msg;
+    use wsts_blueprint::utils::OutputFormat;
+
+    const KEY_IDS: [&[u32]; 3] = [&[0], &[1], &[2]];
+
+    fn key_ids() -> Vec<Vec<u32>> {
+        KEY_IDS.iter().map(|key_ids| key_ids.to_vec()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_honest_parties_sign() {
+        let operators = Operators::new(3);
+        let states = common::honest_keygen(&operators, 2, &key_ids()).await;
+
+        let messages = vec![b"first".to_vec(), b"second".to_vec()];
+        let outcomes =
+            common::sign(&states, &messages, OutputFormat::Bip340, 0, Faults::none()).await;
+
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes.into_values() {
+            let signed = outcome.expect("signing");
+            assert_eq!(signed.len(), messages.len());
+            assert!(signed
+                .iter()
+                .all(|state| state.signature_frost_format.len() == 65));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bad_signature_share_blames_sender() {
+        let operators = Operators::new(3);
+        let states = common::honest_keygen(&operators, 2, &key_ids()).await;
+
+        // Party 1 coordinates, so it is always a signer, and corrupts its share
+        let faults = Faults::none().sends(1, |msg: &mut Msg| {
+            if let Msg::Round2(_) = msg {
+                common::edit(msg, |msg| {
+                    msg["Round2"]["signature_shares"][0]["z_i"] =
+                        serde_json::to_value(Scalar::from(1)).expect("scalar");
+                });
+            }
+        });
+
+        let outcomes = common::sign(
+            &states,
+            &[b"message".to_vec()],
+            OutputFormat::Bip340,
+            1,
+            faults,
+        )
+        .await;
+
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes.into_values() {
+            match outcome {
+                Err(SigningError::Blame { culprits }) => assert_eq!(culprits, vec![1]),
+                Err(err) => panic!("expected party 1 to be blamed, got {err}"),
+                Ok(_) => panic!("expected party 1 to be blamed, but the session succeeded"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bip340_signatures_verify_for_odd_group_keys() {
+        let operators = Operators::new(3);
+
+        // Rerun keygen until the group key has an odd y, which the signers have to lift
+        let states = loop {
+            let states = common::honest_keygen(&operators, 2, &key_ids()).await;
+            if states[0].public_key_frost_format[0] == 0x03 {
+                break states;
+            }
+        };
+
+        let secp = Secp256k1::verification_only();
+        let message = [0x42; 32];
+        for format in [
+            OutputFormat::Bip340,
+            OutputFormat::Taproot(None),
+            OutputFormat::Taproot(Some([0x5a; 32])),
+        ] {
+            let public_key = format
+                .encode_public_key(&states[0].public_key_frost_format)
+                .expect("valid group key");
+            let public_key = XOnlyPublicKey::from_slice(&public_key).expect("x-only key");
+
+            let outcomes =
+                common::sign(&states, &[message.to_vec()], format, 0, Faults::none()).await;
+            for outcome in outcomes.into_values() {
+                let signed = outcome.expect("signing");
+                let signature = format.encode_signature(&signed[0].signature_frost_format);
+                let signature = schnorr::Signature::from_slice(&signature).expect("signature");
+
+                secp.verify_schnorr(&signature, &Message::from_digest(message), &public_key)
+                    .unwrap_or_else(|err| panic!("{format:?} signature does not verify: {err}"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preprocessed_nonces_are_used_once() {
+        let operators = Operators::new(3);
+        let states = common::honest_keygen(&operators, 2, &key_ids()).await;
+
+        let batches = common::preprocess(&states, 1, 2, Faults::none()).await;
+        let pools = batches
+            .into_values()
+            .map(|batch| {
+                let mut pool = NoncePool::default();
+                pool.add(batch.expect("preprocessing"));
+                Arc::new(PoolNonces(pool.into()))
+            })
+            .collect::<Vec<_>>();
+
+        let messages = [b"message".to_vec()];
+        let outcomes = common::sign_with_pools(
+            &states,
+            &pools,
+            &messages,
+            OutputFormat::Frost,
+            0,
+            Faults::none(),
+        )
+        .await;
+        assert!(outcomes.into_values().all(|outcome| outcome.is_ok()));
+        for pool in &pools {
+            assert_eq!(pool.0.lock().unwrap().remaining(), 1);
+        }
+
+        // The coordinator names the slot the first session already used
+        let faults = Faults::none().sends(0, |msg: &mut Msg| {
+            if let Msg::SignerSet(_) = msg {
+                common::edit(msg, |msg| {
+                    msg["SignerSet"]["nonce_slots"] = json!([{ "batch_id": 1, "index": 0 }]);
+                });
+            }
+        });
+        let outcomes =
+            common::sign_with_pools(&states, &pools, &messages, OutputFormat::Frost, 0, faults)
+                .await;
+
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes.into_values() {
+            match outcome {
+                Err(SigningError::ContextError(err)) => assert!(err.contains("do not have")),
+                Err(err) => panic!("expected the reused nonce to be refused, got {err}"),
+                Ok(_) => {
+                    panic!("expected the reused nonce to be refused, but the session succeeded")
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preprocessing_blames_impersonator() {
+        let operators = Operators::new(3);
+        let states = common::honest_keygen(&operators, 2, &key_ids()).await;
+
+        // Party 1 sends its nonces in party 2's name
+        let faults = Faults::none().sends(1, |msg: &mut preprocessing_state_machine::Msg| {
+            common::edit(msg, |msg| msg["PreprocessBroadcast"]["source"] = json!(2));
+        });
+
+        let outcomes = common::preprocess(&states, 1, 2, faults).await;
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes.into_values() {
+            match outcome {
+                Err(SigningError::Blame { culprits }) => assert_eq!(culprits, vec![1]),
+                Err(err) => panic!("expected party 1 to be blamed, got {err}"),
+                Ok(_) => panic!("expected party 1 to be blamed, but preprocessing succeeded"),
+            }
+        }
+    }
+
+    fn public_nonce() -> PublicNonce {
+        PublicNonce::from(&Nonce::random(&mut rand::rngs::OsRng))
+    }
+
+    #[test]
+    fn test_nonce_record_only_moves_forward() {
+        let nonce = public_nonce();
+        let mut record = NonceRecord::new(nonce.clone(), NonceStatus::Generated, 10);
+
+        record
+            .advance(&nonce, NonceStatus::Committed, 11)
+            .expect("generated to committed");
+        assert_eq!(record.status, NonceStatus::Committed);
+        assert_eq!(record.updated_at, 11);
+
+        for status in [NonceStatus::Generated, NonceStatus::Committed] {
+            assert!(matches!(
+                record.advance(&nonce, status, 12),
+                Err(SigningError::NonceReuse(_))
+            ));
+        }
+        assert!(matches!(
+            record.advance(&public_nonce(), NonceStatus::Consumed, 12),
+            Err(SigningError::NonceReuse(_))
+        ));
+
+        record
+            .advance(&nonce, NonceStatus::Consumed, 13)
+            .expect("committed to consumed");
+        assert!(matches!(
+            record.advance(&nonce, NonceStatus::Consumed, 14),
+            Err(SigningError::NonceReuse(_))
+        ));
+        assert_eq!(record.status, NonceStatus::Consumed);
+    }
+
+    #[test]
+    fn test_ledger_refuses_other_nonces_until_expired() {
+        let now = 1_000;
+        let nonces = vec![public_nonce(), public_nonce()];
+        let mut records = Vec::new();
+        advance_ledger(&mut records, &nonces, NonceStatus::Generated, now).expect("new ledger");
+        advance_ledger(&mut records, &nonces, NonceStatus::Committed, now).expect("committed");
+
+        // A rerun of the session brings fresh nonces, which the ledger refuses while it stands
+        let rerun = vec![public_nonce(), public_nonce()];
+        for nonces in [rerun.clone(), rerun[..1].to_vec()] {
+            assert!(matches!(
+                advance_ledger(&mut records, &nonces, NonceStatus::Generated, now + 1),
+                Err(SigningError::NonceReuse(_))
+            ));
+        }
+        assert!(matches!(
+            advance_ledger(&mut records, &nonces, NonceStatus::Generated, now + 1),
+            Err(SigningError::NonceReuse(_))
+        ));
+
+        // Once expired, the ledger starts over with the rerun's nonces
+        let expired = now + NONCE_LEDGER_TTL.as_secs();
+        advance_ledger(&mut records, &rerun, NonceStatus::Generated, expired).expect("expired");
+        assert_eq!(records.len(), rerun.len());
+        assert!(records
+            .iter()
+            .all(|record| record.status == NonceStatus::Generated && record.updated_at == expired));
+        assert!(records
+            .iter()
+            .zip(&rerun)
+            .all(|(record, nonce)| record.public_nonce.D == nonce.D));
+    }
+}
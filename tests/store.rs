@@ -0,0 +1,149 @@
+#[cfg(test)]
+mod store {
+    use blueprint_sdk::crypto::k256::K256Ecdsa;
+    use blueprint_sdk::crypto::KeyType;
+    use blueprint_sdk::testing::tempfile;
+    use wsts_blueprint::compute_execution_hashes;
+    use wsts_blueprint::schema::decode_state;
+    use wsts_blueprint::store::{
+        EncryptedStore, KeyId, StoreBackend, StoreCipher, JSON_STORE_FILE, MIGRATED_JSON_STORE_FILE,
+    };
+
+    const STATE_V0: &[u8] = include_bytes!("fixtures/wsts_state_v0.json");
+
+    fn cipher() -> Result<StoreCipher, Box<dyn std::error::Error>> {
+        let identity = K256Ecdsa::generate_with_seed(None)?;
+        Ok(StoreCipher::new(&identity, None)?)
+    }
+
+    #[test]
+    fn test_switching_to_redb_moves_json_store_aside() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let cipher = cipher()?;
+        let (state, _) = decode_state(STATE_V0)?;
+
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, cipher.clone())?;
+        store.set("1/4/0", &state)?;
+        store.set("1/5/0", &state)?;
+        drop(store);
+
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Redb, cipher.clone())?;
+        assert_eq!(store.keys()?.len(), 2);
+        assert!(!temp_dir.path().join(JSON_STORE_FILE).exists());
+        assert!(temp_dir.path().join(MIGRATED_JSON_STORE_FILE).exists());
+
+        // A key deleted from the database stays deleted across restarts
+        store.delete("1/5/0")?;
+        drop(store);
+
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Redb, cipher.clone())?;
+        assert_eq!(store.keys()?, vec!["1/4/0".to_string()]);
+        drop(store);
+
+        // Going back to the JSON backend would serve stale keys
+        let result = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, cipher);
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_keys_are_moved_to_their_key_id() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Redb, cipher()?)?;
+        let (state, _) = decode_state(STATE_V0)?;
+
+        // Older versions stored keys under the meta hash of their keygen job
+        let (meta_hash, _) = compute_execution_hashes(state.n_signers as u16, 2, 5, "wsts-keygen");
+        store.set(&hex::encode(meta_hash), &state)?;
+
+        assert_eq!(store.migrate_legacy_keys(1, 2)?, 1);
+        let migrated = store.get("1/5/0")?.expect("legacy key is moved");
+        assert_eq!(
+            migrated.public_key_frost_format,
+            state.public_key_frost_format
+        );
+        assert!(store.get(&hex::encode(meta_hash))?.is_none());
+
+        // The search runs once, so keys written under a meta hash afterwards stay put
+        let (meta_hash, _) = compute_execution_hashes(state.n_signers as u16, 2, 6, "wsts-keygen");
+        store.set(&hex::encode(meta_hash), &state)?;
+        assert_eq!(store.migrate_legacy_keys(1, 2)?, 0);
+        assert!(store.get("1/6/0")?.is_none());
+        assert_eq!(store.keys()?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_id_round_trips_through_store_key() {
+        let key_id = KeyId::new(1, 5);
+        assert_eq!(key_id.version, KeyId::INITIAL_VERSION);
+        assert_eq!(key_id.store_key(), "1/5/0");
+        assert_eq!(key_id.store_key().parse::<KeyId>(), Ok(key_id));
+        assert!("1/5".parse::<KeyId>().is_err());
+    }
+
+    #[test]
+    fn test_plaintext_store_is_encrypted() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let cipher = cipher()?;
+        let (state, _) = decode_state(STATE_V0)?;
+
+        // Older versions kept every state in the store file as plain JSON
+        let plaintext: serde_json::Value = serde_json::from_slice(STATE_V0)?;
+        let store_path = temp_dir.path().join(JSON_STORE_FILE);
+        std::fs::write(
+            &store_path,
+            serde_json::to_vec(&serde_json::json!({ "1/4/0": plaintext }))?,
+        )?;
+
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, cipher)?;
+        let migrated = store.get("1/4/0")?.expect("plaintext key is kept");
+        assert_eq!(
+            migrated.public_key_frost_format,
+            state.public_key_frost_format
+        );
+
+        // Nothing of the state is left in the clear on disk
+        let on_disk: serde_json::Value = serde_json::from_slice(&std::fs::read(&store_path)?)?;
+        let record = &on_disk["1/4/0"];
+        assert!(record["nonce"].is_array() && record["ciphertext"].is_array());
+        assert_eq!(record.as_object().map(|record| record.len()), Some(2));
+        assert!(!temp_dir.path().join("wsts.json.tmp").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_store_key_fails_to_open() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let identity = K256Ecdsa::generate_with_seed(None)?;
+        let (state, _) = decode_state(STATE_V0)?;
+
+        let cipher = StoreCipher::new(&identity, Some("passphrase"))?;
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, cipher.clone())?;
+        store.set("1/4/0", &state)?;
+        drop(store);
+
+        let other_identity = K256Ecdsa::generate_with_seed(None)?;
+        let wrong_ciphers = [
+            StoreCipher::new(&identity, Some("wrong passphrase"))?,
+            StoreCipher::new(&identity, None)?,
+            StoreCipher::new(&other_identity, Some("passphrase"))?,
+        ];
+        for wrong_cipher in wrong_ciphers {
+            let result = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, wrong_cipher);
+            let err = result
+                .err()
+                .expect("the store does not open with the wrong key");
+            assert!(err.to_string().contains("Failed to decrypt"), "{err}");
+        }
+
+        // Failing to open leaves the store as it was
+        let store = EncryptedStore::open(temp_dir.path(), StoreBackend::Json, cipher)?;
+        assert!(store.get("1/4/0")?.is_some());
+
+        Ok(())
+    }
+}
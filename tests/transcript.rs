@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod transcript {
+    use wsts_blueprint::transcript::{SigningTranscript, TranscriptSigner};
+
+    type Edit = fn(&mut SigningTranscript);
+
+    fn transcript() -> SigningTranscript {
+        SigningTranscript {
+            keygen_call_id: 4,
+            call_id: 9,
+            public_key: vec![2; 33],
+            signers: vec![
+                TranscriptSigner {
+                    party_id: 0,
+                    key_ids: vec![0, 1],
+                    nonce_commitments: [vec![2; 33], vec![3; 33]],
+                },
+                TranscriptSigner {
+                    party_id: 2,
+                    key_ids: vec![2],
+                    nonce_commitments: [vec![4; 33], vec![5; 33]],
+                },
+            ],
+            message_digest: [6; 32],
+            signature: vec![7; 65],
+        }
+    }
+
+    #[test]
+    fn test_digest_survives_serialization() -> Result<(), Box<dyn std::error::Error>> {
+        let transcript = transcript();
+        let bytes = serde_json::to_vec(&transcript)?;
+        let restored: SigningTranscript = serde_json::from_slice(&bytes)?;
+        assert_eq!(restored.digest(), transcript.digest());
+
+        let pretty = serde_json::to_vec_pretty(&transcript)?;
+        let restored: SigningTranscript = serde_json::from_slice(&pretty)?;
+        assert_eq!(restored.digest(), transcript.digest());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_covers_every_field() {
+        let edits: [(&str, Edit); 10] = [
+            ("keygen_call_id", |t| t.keygen_call_id += 1),
+            ("call_id", |t| t.call_id += 1),
+            ("public_key", |t| t.public_key[32] ^= 1),
+            ("signer party_id", |t| t.signers[1].party_id = 1),
+            ("signer key_ids", |t| t.signers[1].key_ids.push(3)),
+            ("signer D", |t| t.signers[0].nonce_commitments[0][32] ^= 1),
+            ("signer E", |t| t.signers[0].nonce_commitments[1][32] ^= 1),
+            ("signers", |t| {
+                t.signers.pop();
+            }),
+            ("message_digest", |t| t.message_digest[0] ^= 1),
+            ("signature", |t| t.signature[64] ^= 1),
+        ];
+
+        let digest = transcript().digest();
+        for (field, edit) in edits {
+            let mut edited = transcript();
+            edit(&mut edited);
+            assert_ne!(edited.digest(), digest, "digest ignores {field}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+#[cfg(test)]
+mod utils {
+    use bitcoin::key::{Secp256k1, TapTweak, XOnlyPublicKey};
+    use bitcoin::TapNodeHash;
+    use p256k1::point::{Point, G};
+    use p256k1::scalar::Scalar;
+    use wsts_blueprint::keygen::KeygenError;
+    use wsts_blueprint::utils::{allocate_party_key_ids, generate_party_key_ids, OutputFormat};
+
+    /// A random group key whose y coordinate has the given parity
+    fn group_key(even_y: bool) -> Point {
+        loop {
+            let key = Scalar::random(&mut rand::rngs::OsRng) * G;
+            if key.has_even_y() == even_y {
+                return key;
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_party_key_ids_spreads_remainder() {
+        let key_ids = generate_party_key_ids(4, 10);
+        assert_eq!(
+            key_ids,
+            vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7], vec![8, 9]]
+        );
+    }
+
+    #[test]
+    fn test_generate_party_key_ids_even_split() {
+        let key_ids = generate_party_key_ids(3, 6);
+        assert_eq!(key_ids, vec![vec![0, 1], vec![2, 3], vec![4, 5]]);
+    }
+
+    #[test]
+    fn test_allocate_defaults_to_one_key_id_per_party() -> Result<(), KeygenError> {
+        let key_ids = allocate_party_key_ids(3, 0, 2, &[])?;
+        assert_eq!(key_ids, vec![vec![0], vec![1], vec![2]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_weighted() -> Result<(), KeygenError> {
+        let key_ids = allocate_party_key_ids(3, 6, 4, &[3, 1, 2])?;
+        assert_eq!(key_ids, vec![vec![0, 1, 2], vec![3], vec![4, 5]]);
+
+        // k may be left out and derived from the weights
+        assert_eq!(allocate_party_key_ids(3, 0, 4, &[3, 1, 2])?, key_ids);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_rejects_k_not_sum_of_weights() {
+        let result = allocate_party_key_ids(3, 7, 4, &[3, 1, 2]);
+        assert!(matches!(result, Err(KeygenError::SetupError(_))));
+    }
+
+    #[test]
+    fn test_allocate_rejects_zero_weight() {
+        let result = allocate_party_key_ids(3, 0, 2, &[2, 0, 2]);
+        assert!(matches!(result, Err(KeygenError::SetupError(_))));
+    }
+
+    #[test]
+    fn test_allocate_rejects_wrong_number_of_weights() {
+        let result = allocate_party_key_ids(3, 0, 2, &[2, 2]);
+        assert!(matches!(result, Err(KeygenError::SetupError(_))));
+    }
+
+    #[test]
+    fn test_allocate_rejects_unreachable_threshold() {
+        let result = allocate_party_key_ids(3, 3, 3, &[]);
+        assert!(matches!(result, Err(KeygenError::SetupError(_))));
+
+        let result = allocate_party_key_ids(3, 0, 6, &[3, 1, 2]);
+        assert!(matches!(result, Err(KeygenError::SetupError(_))));
+    }
+
+    #[test]
+    fn test_taproot_key_matches_bitcoin_tap_tweak() {
+        let secp = Secp256k1::verification_only();
+        let merkle_root = [0x5a; 32];
+
+        // An odd-y group key must be lifted to even y before it is tweaked
+        for even_y in [true, false] {
+            let group_key = group_key(even_y);
+            let internal_key =
+                XOnlyPublicKey::from_slice(&group_key.x().to_bytes()).expect("x-only key");
+
+            for root in [None, Some(merkle_root)] {
+                let (expected, _) =
+                    internal_key.tap_tweak(&secp, root.map(TapNodeHash::assume_hidden));
+
+                let format = OutputFormat::Taproot(root);
+                let encoded = format
+                    .encode_public_key(&group_key.compress().data)
+                    .expect("valid group key");
+                assert_eq!(encoded, expected.to_x_only_public_key().serialize());
+
+                // The parties sign for that same key
+                let key = format.signature_key(&group_key);
+                assert!(key.public_key.has_even_y());
+                assert_eq!(key.key_sign * group_key + key.tweak * G, key.public_key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bip340_key_is_the_lifted_group_key() {
+        for even_y in [true, false] {
+            let group_key = group_key(even_y);
+            let key = OutputFormat::Bip340.signature_key(&group_key);
+
+            assert!(key.public_key.has_even_y());
+            assert_eq!(key.public_key.x(), group_key.x());
+            assert_eq!(key.key_sign * group_key, key.public_key);
+            assert_eq!(
+                OutputFormat::Bip340.encode_public_key(&group_key.compress().data),
+                Ok(group_key.x().to_bytes().to_vec())
+            );
+        }
+    }
+}
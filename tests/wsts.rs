@@ -1,24 +1,44 @@
 #[cfg(test)]
 mod e2e {
     use blueprint_sdk::logging::setup_log;
-    use blueprint_sdk::testing::utils::tangle::{InputValue, TangleTestHarness};
     use blueprint_sdk::testing::tempfile;
     use blueprint_sdk::testing::utils::harness::TestHarness;
     use blueprint_sdk::testing::utils::runner::TestEnv;
+    use blueprint_sdk::testing::utils::tangle::{InputValue, TangleTestHarness};
+    use blueprint_sdk::macros::ext::contexts::tangle::TangleClientContext;
     use blueprint_sdk::tokio;
+    use gadget_macros::ext::clients::GadgetServicesClient;
     use wsts_blueprint::context::WstsContext;
     use wsts_blueprint::keygen::KEYGEN_JOB_ID;
-    use wsts_blueprint::signing::SIGN_JOB_ID;
+    use wsts_blueprint::preprocessing::PREPROCESS_NONCES_JOB_ID;
+    use wsts_blueprint::psbt::SIGN_PSBT_JOB_ID;
+    use wsts_blueprint::signing::{SIGN_BATCH_JOB_ID, SIGN_JOB_ID, SIGN_WITH_DETAILS_JOB_ID};
     use wsts_blueprint::tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
 
     const T: usize = 2;
+    const CONCURRENT_SIGNING_JOBS: usize = 24;
+    const BATCH_SIZE: usize = 8;
+    const FROST_FORMAT: u8 = 0;
+    const BIP340_FORMAT: u8 = 1;
+    const TAPROOT_FORMAT: u8 = 2;
+    const RAW_MODE: u8 = 0;
+    const PREHASHED_MODE: u8 = 1;
+    const TAGGED_MODE: u8 = 2;
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn test_blueprint() -> Result<(), Box<dyn std::error::Error>> {
-        setup_log();
+    /// Spins up the test network with the keygen, preprocessing and signing jobs registered,
+    /// returning the harness and the service id
+    async fn setup_blueprint(
+        temp_dir: tempfile::TempDir,
+    ) -> Result<(TangleTestHarness, u64), Box<dyn std::error::Error>> {
+        let (harness, service_id, _) = setup_blueprint_with_context(temp_dir).await?;
+        Ok((harness, service_id))
+    }
 
+    /// Like [`setup_blueprint`], also returning the context the jobs run with
+    async fn setup_blueprint_with_context(
+        temp_dir: tempfile::TempDir,
+    ) -> Result<(TangleTestHarness, u64, WstsContext), Box<dyn std::error::Error>> {
         // Initialize test harness (node, keys, deployment)
-        let temp_dir = tempfile::TempDir::new()?;
         let harness = TangleTestHarness::setup(temp_dir).await?;
         let env = harness.env().clone();
 
@@ -31,23 +51,164 @@ mod e2e {
                 .await?;
 
         let signing_handler =
-            wsts_blueprint::signing::SignEventHandler::new(&env.clone(), blueprint_ctx).await?;
+            wsts_blueprint::signing::SignEventHandler::new(&env.clone(), blueprint_ctx.clone())
+                .await?;
+
+        let detailed_signing_handler = wsts_blueprint::signing::SignWithDetailsEventHandler::new(
+            &env.clone(),
+            blueprint_ctx.clone(),
+        )
+        .await?;
+
+        let batch_signing_handler = wsts_blueprint::signing::SignBatchEventHandler::new(
+            &env.clone(),
+            blueprint_ctx.clone(),
+        )
+        .await?;
+
+        let psbt_signing_handler =
+            wsts_blueprint::psbt::SignPsbtEventHandler::new(&env.clone(), blueprint_ctx.clone())
+                .await?;
+
+        let preprocessing_handler =
+            wsts_blueprint::preprocessing::PreprocessNoncesEventHandler::new(
+                &env.clone(),
+                blueprint_ctx.clone(),
+            )
+            .await?;
 
         // Setup service
         let (mut test_env, service_id) = harness.setup_services().await?;
         test_env.add_job(keygen_handler);
         test_env.add_job(signing_handler);
+        test_env.add_job(detailed_signing_handler);
+        test_env.add_job(batch_signing_handler);
+        test_env.add_job(psbt_signing_handler);
+        test_env.add_job(preprocessing_handler);
 
         tokio::spawn(async move {
             test_env.run_runner().await.unwrap();
         });
 
+        Ok((harness, service_id, blueprint_ctx))
+    }
+
+    fn keygen_inputs(output_format: u8, taproot_merkle_root: &[u8]) -> Vec<InputValue> {
+        vec![
+            InputValue::Uint16(T as u16),
+            InputValue::Uint16(0),
+            InputValue::List(BoundedVec(vec![])),
+            InputValue::Uint8(output_format),
+            bytes_input(taproot_merkle_root),
+        ]
+    }
+
+    /// Like [`sign_inputs`], for a FROST signature over `message` in the given message mode
+    fn sign_inputs_with_mode(
+        keygen_call_id: u64,
+        message: &[u8],
+        message_mode: u8,
+        domain_tag: &[u8],
+    ) -> Vec<InputValue> {
+        let mut inputs = sign_inputs(keygen_call_id, message, FROST_FORMAT, &[]);
+        inputs.truncate(4);
+        inputs.push(InputValue::Uint8(message_mode));
+        inputs.push(bytes_input(domain_tag));
+        inputs
+    }
+
+    fn bytes_input(bytes: &[u8]) -> InputValue {
+        InputValue::List(BoundedVec(
+            bytes.iter().copied().map(InputValue::Uint8).collect(),
+        ))
+    }
+
+    fn output_bytes(output: &InputValue) -> Vec<u8> {
+        match output {
+            InputValue::List(BoundedVec(values)) => values
+                .iter()
+                .map(|value| match value {
+                    InputValue::Uint8(byte) => *byte,
+                    _ => panic!("expected a byte"),
+                })
+                .collect(),
+            _ => panic!("expected a list of bytes"),
+        }
+    }
+
+    fn output_list(output: &InputValue) -> &[InputValue] {
+        match output {
+            InputValue::List(BoundedVec(values)) => values,
+            _ => panic!("expected a list"),
+        }
+    }
+
+    /// Returns the field called `name` of a struct returned by a job
+    fn output_field<'a>(output: &'a InputValue, name: &str) -> &'a InputValue {
+        match output {
+            InputValue::Struct(_, fields) => fields
+                .0
+                .iter()
+                .find(|(field, _)| field.0 .0 == name.as_bytes())
+                .map(|(_, value)| value)
+                .unwrap_or_else(|| panic!("expected a field {name}")),
+            _ => panic!("expected a struct"),
+        }
+    }
+
+    /// Checks a 65-byte FROST or 64-byte BIP340 signature over `message` against a 33-byte
+    /// compressed or 32-byte x-only public key
+    fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let with_even_y = |bytes: &[u8], len: usize| {
+            if bytes.len() == len {
+                [&[0x02], bytes].concat()
+            } else {
+                bytes.to_vec()
+            }
+        };
+
+        let Ok(public_key) =
+            frost_secp256k1_tr::VerifyingKey::deserialize(&with_even_y(public_key, 32))
+        else {
+            return false;
+        };
+        let Ok(signature) = frost_secp256k1_tr::Signature::deserialize(&with_even_y(signature, 64))
+        else {
+            return false;
+        };
+
+        public_key.verify(message, &signature).is_ok()
+    }
+
+    fn sign_inputs(
+        keygen_call_id: u64,
+        message: &[u8],
+        output_format: u8,
+        taproot_merkle_root: &[u8],
+    ) -> Vec<InputValue> {
+        vec![
+            InputValue::Uint64(keygen_call_id),
+            bytes_input(message),
+            InputValue::Uint8(output_format),
+            bytes_input(taproot_merkle_root),
+            InputValue::Uint8(RAW_MODE),
+            bytes_input(&[]),
+        ]
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blueprint() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
         // Execute job and verify result
         let keygen_result = harness
             .execute_job(
                 service_id,
                 KEYGEN_JOB_ID,
-                vec![(InputValue::Uint16(T as u16))],
+                keygen_inputs(FROST_FORMAT, &[]),
                 vec![],
             )
             .await?;
@@ -58,20 +219,556 @@ mod e2e {
             .execute_job(
                 service_id,
                 SIGN_JOB_ID,
-                vec![
-                    InputValue::Uint64(keygen_result.call_id),
-                    InputValue::List(BoundedVec(vec![
-                        InputValue::Uint8(1),
-                        InputValue::Uint8(2),
-                        InputValue::Uint8(3),
-                    ])),
-                ],
+                sign_inputs(keygen_result.call_id, &[1, 2, 3], FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        assert_eq!(results.service_id, service_id);
+
+        // The sign job returns the bare signature, in the FROST format by default
+        let signature = output_bytes(&results.result[0]);
+        assert_eq!(signature.len(), 65);
+        assert!(verify_signature(
+            &output_bytes(&keygen_result.result[0]),
+            &[1, 2, 3],
+            &signature
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_weighted_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id, context) = setup_blueprint_with_context(temp_dir).await?;
+
+        // The first operator holds two key ids and every other operator one, so the first
+        // operator plus any other meets the threshold
+        let (_, operators) = context
+            .tangle_client()
+            .await?
+            .get_party_index_and_operators()
+            .await?;
+        let weights = (0..operators.len())
+            .map(|j| InputValue::Uint16(if j == 0 { 2 } else { 1 }))
+            .collect();
+
+        let mut inputs = keygen_inputs(FROST_FORMAT, &[]);
+        inputs[2] = InputValue::List(BoundedVec(weights));
+        let keygen_result = harness
+            .execute_job(service_id, KEYGEN_JOB_ID, inputs, vec![])
+            .await?;
+
+        let results = harness
+            .execute_job(
+                service_id,
+                SIGN_JOB_ID,
+                sign_inputs(keygen_result.call_id, &[1, 2, 3], FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        assert!(verify_signature(
+            &output_bytes(&keygen_result.result[0]),
+            &[1, 2, 3],
+            &output_bytes(&results.result[0])
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_message_modes() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        let public_key = output_bytes(&keygen_result.result[0]);
+
+        // tagged_hash(tag, msg) = sha256(sha256(tag) || sha256(tag) || msg), as in BIP340
+        let tagged_hash = |tag: &[u8], message: &[u8]| {
+            use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+            let tag_hash = sha256::Hash::hash(tag);
+            let mut engine = sha256::Hash::engine();
+            engine.input(tag_hash.as_ref());
+            engine.input(tag_hash.as_ref());
+            engine.input(message);
+            sha256::Hash::from_engine(engine).to_byte_array().to_vec()
+        };
+
+        let domain_tag = b"wsts-blueprint/test";
+        let requests = [
+            (RAW_MODE, b"hello".to_vec(), Vec::new(), b"hello".to_vec()),
+            (PREHASHED_MODE, vec![7u8; 32], Vec::new(), vec![7u8; 32]),
+            (
+                TAGGED_MODE,
+                b"hello".to_vec(),
+                domain_tag.to_vec(),
+                tagged_hash(domain_tag, b"hello"),
+            ),
+        ];
+
+        for (mode, message, domain_tag, signed_message) in requests {
+            let inputs = sign_inputs_with_mode(keygen_result.call_id, &message, mode, &domain_tag);
+            let results = harness
+                .execute_job(service_id, SIGN_WITH_DETAILS_JOB_ID, inputs, vec![])
+                .await?;
+
+            assert_eq!(results.service_id, service_id);
+
+            // The output says exactly what was signed, and the signature is over those bytes
+            let output = &results.result[0];
+            assert!(matches!(
+                output_field(output, "message_mode"),
+                InputValue::Uint8(m) if *m == mode
+            ));
+            assert_eq!(output_bytes(output_field(output, "domain_tag")), domain_tag);
+            assert_eq!(
+                output_bytes(output_field(output, "signed_message")),
+                signed_message
+            );
+
+            let signature = output_bytes(output_field(output, "signature"));
+            assert!(
+                verify_signature(&public_key, &signed_message, &signature),
+                "signature in mode {mode} is not over the signed message"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_bip340_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(BIP340_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        assert_eq!(keygen_result.service_id, service_id);
+
+        let public_key = output_bytes(&keygen_result.result[0]);
+        assert_eq!(public_key.len(), 32);
+
+        // Sign several messages, so that both parities of R are likely to come up
+        for i in 0..4u8 {
+            let message = [i, 1, 2, 3];
+            let results = harness
+                .execute_job(
+                    service_id,
+                    SIGN_JOB_ID,
+                    sign_inputs(keygen_result.call_id, &message, BIP340_FORMAT, &[]),
+                    vec![],
+                )
+                .await?;
+
+            assert_eq!(results.service_id, service_id);
+
+            let signature = output_bytes(&results.result[0]);
+            assert_eq!(signature.len(), 64);
+            assert!(verify_signature(&public_key, &message, &signature));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_taproot_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        // Once for a key-path-only output, once for an output with a script tree
+        for merkle_root in [vec![], vec![7u8; 32]] {
+            let keygen_result = harness
+                .execute_job(
+                    service_id,
+                    KEYGEN_JOB_ID,
+                    keygen_inputs(TAPROOT_FORMAT, &merkle_root),
+                    vec![],
+                )
+                .await?;
+
+            assert_eq!(keygen_result.service_id, service_id);
+
+            // Keygen returns the tweaked output key, which the signature must verify against
+            let output_key = output_bytes(&keygen_result.result[0]);
+            assert_eq!(output_key.len(), 32);
+
+            let message = [1, 2, 3];
+            let results = harness
+                .execute_job(
+                    service_id,
+                    SIGN_JOB_ID,
+                    sign_inputs(
+                        keygen_result.call_id,
+                        &message,
+                        TAPROOT_FORMAT,
+                        &merkle_root,
+                    ),
+                    vec![],
+                )
+                .await?;
+
+            assert_eq!(results.service_id, service_id);
+
+            let signature = output_bytes(&results.result[0]);
+            assert_eq!(signature.len(), 64);
+            assert!(verify_signature(&output_key, &message, &signature));
+        }
+
+        Ok(())
+    }
+
+    /// Builds a PSBT with a single input spending the Taproot output of `internal_key` and
+    /// `merkle_root`
+    fn taproot_psbt(
+        internal_key: bitcoin::key::XOnlyPublicKey,
+        merkle_root: Option<bitcoin::TapNodeHash>,
+    ) -> bitcoin::Psbt {
+        use bitcoin::{
+            absolute, transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+        };
+
+        let secp = bitcoin::key::Secp256k1::verification_only();
+        let utxo = TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new_p2tr(&secp, internal_key, merkle_root),
+        };
+
+        let unsigned_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: utxo.script_pubkey.clone(),
+            }],
+        };
+
+        let mut psbt = bitcoin::Psbt::from_unsigned_tx(unsigned_tx).expect("unsigned tx");
+        psbt.inputs[0].witness_utxo = Some(utxo);
+        psbt.inputs[0].tap_internal_key = Some(internal_key);
+        psbt.inputs[0].tap_merkle_root = merkle_root;
+        psbt
+    }
+
+    /// Checks the key-path signature in the finalized input of `signed` against the Taproot
+    /// output key that `psbt`'s input spends, as the network would
+    fn verify_key_spend(psbt: &bitcoin::Psbt, signed: &bitcoin::Psbt) -> bool {
+        use bitcoin::hashes::Hash;
+        use bitcoin::key::TapTweak;
+        use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+
+        let input = &psbt.inputs[0];
+        let utxo = input.witness_utxo.clone().expect("witness utxo");
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&[utxo]), TapSighashType::Default)
+            .expect("sighash");
+
+        let secp = bitcoin::key::Secp256k1::verification_only();
+        let (output_key, _) = input
+            .tap_internal_key
+            .expect("internal key")
+            .tap_tweak(&secp, input.tap_merkle_root);
+
+        let Some(signature) = signed.inputs[0]
+            .final_script_witness
+            .as_ref()
+            .and_then(|witness| witness.nth(0))
+            .and_then(|signature| {
+                bitcoin::secp256k1::schnorr::Signature::from_slice(signature).ok()
+            })
+        else {
+            return false;
+        };
+
+        let message = bitcoin::secp256k1::Message::from_digest(sighash.to_byte_array());
+        secp.verify_schnorr(&signature, &message, &output_key.to_x_only_public_key())
+            .is_ok()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_psbt_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        // Generate keys until the group key has an odd y, which the operators have to lift to
+        // the even-y internal key the PSBT commits to
+        let keygen_result = loop {
+            let keygen_result = harness
+                .execute_job(
+                    service_id,
+                    KEYGEN_JOB_ID,
+                    keygen_inputs(FROST_FORMAT, &[]),
+                    vec![],
+                )
+                .await?;
+
+            if output_bytes(&keygen_result.result[0])[0] == 0x03 {
+                break keygen_result;
+            }
+        };
+
+        let internal_key =
+            bitcoin::key::XOnlyPublicKey::from_slice(&output_bytes(&keygen_result.result[0])[1..])?;
+
+        // Once for a key-path-only output, once for an output with a script tree
+        for merkle_root in [None, Some(bitcoin::TapNodeHash::assume_hidden([7u8; 32]))] {
+            let psbt = taproot_psbt(internal_key, merkle_root);
+
+            let results = harness
+                .execute_job(
+                    service_id,
+                    SIGN_PSBT_JOB_ID,
+                    vec![
+                        InputValue::Uint64(keygen_result.call_id),
+                        bytes_input(&psbt.serialize()),
+                    ],
+                    vec![],
+                )
+                .await?;
+
+            assert_eq!(results.service_id, service_id);
+
+            let signed = bitcoin::Psbt::deserialize(&output_bytes(&results.result[0]))?;
+            let witness = signed.inputs[0]
+                .final_script_witness
+                .as_ref()
+                .expect("input is finalized");
+            assert_eq!(witness.len(), 1);
+            assert_eq!(witness.nth(0).map(<[u8]>::len), Some(64));
+            assert!(verify_key_spend(&psbt, &signed));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(FROST_FORMAT, &[]),
                 vec![],
             )
             .await?;
 
+        let messages: Vec<Vec<u8>> = (0..BATCH_SIZE as u8).map(|i| vec![i, 1, 2, 3]).collect();
+        let inputs = vec![
+            InputValue::Uint64(keygen_result.call_id),
+            InputValue::List(BoundedVec(
+                messages
+                    .iter()
+                    .map(|message| bytes_input(message))
+                    .collect(),
+            )),
+            InputValue::Uint8(FROST_FORMAT),
+            InputValue::List(BoundedVec(vec![])),
+            InputValue::Uint8(RAW_MODE),
+            InputValue::List(BoundedVec(vec![])),
+        ];
+
+        let results = harness
+            .execute_job(service_id, SIGN_BATCH_JOB_ID, inputs, vec![])
+            .await?;
+
         assert_eq!(results.service_id, service_id);
 
+        // One signature per message, in the order the messages were given
+        let public_key = output_bytes(&keygen_result.result[0]);
+        let outputs = output_list(&results.result[0]);
+        assert_eq!(outputs.len(), BATCH_SIZE);
+        for (output, message) in outputs.iter().zip(&messages) {
+            let signature = output_bytes(output_field(output, "signature"));
+            assert_eq!(
+                output_bytes(output_field(output, "signed_message")),
+                *message
+            );
+            assert_eq!(signature.len(), 65);
+            assert!(verify_signature(&public_key, message, &signature));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        // Submit every signing job before waiting on any of them, so the operators run the
+        // sessions against the same key concurrently
+        let mut jobs = Vec::with_capacity(CONCURRENT_SIGNING_JOBS);
+        for i in 0..CONCURRENT_SIGNING_JOBS {
+            let message = [i as u8, 1, 2, 3];
+            let job = harness
+                .submit_job(
+                    service_id,
+                    SIGN_JOB_ID,
+                    sign_inputs(keygen_result.call_id, &message, FROST_FORMAT, &[]),
+                )
+                .await?;
+            jobs.push(job);
+        }
+
+        for job in jobs {
+            let call_id = job.call_id;
+            let result = harness.wait_for_job_execution(service_id, job).await?;
+            assert_eq!(result.service_id, service_id);
+            assert_eq!(result.call_id, call_id);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_preprocessed_signing() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id) = setup_blueprint(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+        let public_key = output_bytes(&keygen_result.result[0]);
+
+        let preprocess = |count: u16| {
+            vec![
+                InputValue::Uint64(keygen_result.call_id),
+                InputValue::Uint16(count),
+            ]
+        };
+
+        let results = harness
+            .execute_job(service_id, PREPROCESS_NONCES_JOB_ID, preprocess(4), vec![])
+            .await?;
+        assert!(matches!(results.result[0], InputValue::Uint64(4)));
+
+        let message = [9u8, 1, 2, 3];
+        let results = harness
+            .execute_job(
+                service_id,
+                SIGN_JOB_ID,
+                sign_inputs(keygen_result.call_id, &message, FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+        let signature = output_bytes(&results.result[0]);
+        assert!(verify_signature(&public_key, &message, &signature));
+
+        // The session used one of the preprocessed nonces, so adding one more leaves four
+        let results = harness
+            .execute_job(service_id, PREPROCESS_NONCES_JOB_ID, preprocess(1), vec![])
+            .await?;
+        assert!(matches!(results.result[0], InputValue::Uint64(4)));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transcript_lookup() -> Result<(), Box<dyn std::error::Error>> {
+        setup_log();
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let (harness, service_id, context) = setup_blueprint_with_context(temp_dir).await?;
+
+        let keygen_result = harness
+            .execute_job(
+                service_id,
+                KEYGEN_JOB_ID,
+                keygen_inputs(FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        let message = [5u8, 1, 2, 3];
+        let results = harness
+            .execute_job(
+                service_id,
+                SIGN_WITH_DETAILS_JOB_ID,
+                sign_inputs(keygen_result.call_id, &message, FROST_FORMAT, &[]),
+                vec![],
+            )
+            .await?;
+
+        // The transcript is stored under the digest the job returned, and hashes to it
+        let output = &results.result[0];
+        let transcript_digest = output_bytes(output_field(output, "transcript_digest"));
+        let transcript = context
+            .transcripts
+            .get(&hex::encode(&transcript_digest))
+            .expect("transcript is stored under its digest");
+        assert_eq!(transcript.digest().to_vec(), transcript_digest);
+
+        assert_eq!(transcript.keygen_call_id, keygen_result.call_id);
+        assert_eq!(transcript.call_id, results.call_id);
+        assert_eq!(
+            transcript.public_key,
+            output_bytes(&keygen_result.result[0])
+        );
+        assert_eq!(
+            transcript.signature,
+            output_bytes(output_field(output, "signature"))
+        );
+        assert_eq!(
+            transcript.message_digest,
+            wsts_blueprint::compute_sha256_hash!(&message)
+        );
+        assert!(transcript.signers.len() >= T);
+
         Ok(())
     }
 }